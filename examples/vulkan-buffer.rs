@@ -83,14 +83,11 @@ fn main() {
     };
 
     // Setting up the allocator
-    let mut allocator = Allocator::new(&AllocatorCreateDesc {
-        instance: instance.clone(),
-        device: device.clone(),
-        physical_device: pdevice,
-        debug_settings: Default::default(),
-        buffer_device_address: false,
-        allocation_sizes: Default::default(),
-    })
+    let mut allocator = Allocator::new(&AllocatorCreateDesc::new(
+        instance.clone(),
+        device.clone(),
+        pdevice,
+    ))
     .unwrap();
 
     // Test allocating Gpu Only memory
@@ -110,6 +107,7 @@ fn main() {
                 linear: true,
                 allocation_scheme: AllocationScheme::GpuAllocatorManaged,
                 name: "Test allocation (Gpu Only)",
+                ..Default::default()
             })
             .unwrap();
 
@@ -143,6 +141,7 @@ fn main() {
                 linear: true,
                 allocation_scheme: AllocationScheme::GpuAllocatorManaged,
                 name: "Test allocation (Cpu to Gpu)",
+                ..Default::default()
             })
             .unwrap();
 
@@ -176,6 +175,7 @@ fn main() {
                 linear: true,
                 allocation_scheme: AllocationScheme::GpuAllocatorManaged,
                 name: "Test allocation (Gpu to Cpu)",
+                ..Default::default()
             })
             .unwrap();
 
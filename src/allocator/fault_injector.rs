@@ -0,0 +1,209 @@
+use log::debug;
+
+use crate::{AllocationError, FaultInjection, FaultInjectionError, FaultInjectionTrigger};
+
+/// Tracks the state needed to apply [`crate::AllocatorDebugSettings::fault_injection`] to the
+/// creation of memory blocks.
+#[derive(Debug)]
+pub(crate) struct FaultInjector {
+    fault_injection: Option<FaultInjection>,
+    created_blocks: u64,
+    live_bytes: u64,
+    rng_state: u64,
+}
+
+impl FaultInjector {
+    pub(crate) fn new(fault_injection: Option<FaultInjection>) -> Self {
+        let rng_state = match fault_injection {
+            Some(FaultInjection {
+                trigger: FaultInjectionTrigger::Random { seed, .. },
+                ..
+            }) => seed,
+            _ => 0,
+        };
+
+        Self {
+            fault_injection,
+            created_blocks: 0,
+            live_bytes: 0,
+            rng_state,
+        }
+    }
+
+    /// `SplitMix64`, which is good enough to spread failures and keeps sequences reproducible.
+    fn next_random(&mut self) -> f32 {
+        self.rng_state = self.rng_state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        // Use the upper 24 bits, which is exactly the precision of an `f32` mantissa.
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Decides whether the creation of a memory block of `size` bytes has to fail.
+    pub(crate) fn next_fault(&mut self, size: u64) -> Option<FaultInjectionError> {
+        let fault_injection = self.fault_injection?;
+
+        let fail = match fault_injection.trigger {
+            FaultInjectionTrigger::AfterBlockCount(count) => self.created_blocks >= count,
+            FaultInjectionTrigger::ByteLimit(limit) => self.live_bytes + size > limit,
+            FaultInjectionTrigger::Random { probability, .. } => self.next_random() < probability,
+        };
+
+        if fail {
+            debug!(
+                "Injecting {:?} for a memory block of {} bytes.",
+                fault_injection.error, size
+            );
+            Some(fault_injection.error)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn block_created(&mut self, size: u64) {
+        self.created_blocks += 1;
+        self.live_bytes += size;
+    }
+
+    pub(crate) fn block_destroyed(&mut self, size: u64) {
+        self.live_bytes -= size;
+    }
+}
+
+impl From<FaultInjectionError> for AllocationError {
+    fn from(error: FaultInjectionError) -> Self {
+        match error {
+            FaultInjectionError::OutOfMemory => Self::OutOfMemory,
+            FaultInjectionError::FailedToMap => {
+                Self::FailedToMap("Injected by `AllocatorDebugSettings::fault_injection`".into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    fn injector(trigger: FaultInjectionTrigger) -> FaultInjector {
+        FaultInjector::new(Some(FaultInjection {
+            trigger,
+            error: FaultInjectionError::OutOfMemory,
+        }))
+    }
+
+    fn faults(injector: &mut FaultInjector, count: usize) -> Vec<bool> {
+        (0..count)
+            .map(|_| injector.next_fault(1).is_some())
+            .collect()
+    }
+
+    #[test]
+    fn disabled_never_fails() {
+        let mut injector = FaultInjector::new(None);
+        for _ in 0..100 {
+            assert_eq!(injector.next_fault(u64::MAX), None);
+            injector.block_created(1);
+        }
+    }
+
+    #[test]
+    fn after_block_count() {
+        let mut injector = injector(FaultInjectionTrigger::AfterBlockCount(2));
+
+        for _ in 0..2 {
+            assert_eq!(injector.next_fault(1), None);
+            injector.block_created(1);
+        }
+        assert_eq!(
+            injector.next_fault(1),
+            Some(FaultInjectionError::OutOfMemory)
+        );
+
+        // Destroying blocks does not reset the count.
+        injector.block_destroyed(1);
+        assert_eq!(
+            injector.next_fault(1),
+            Some(FaultInjectionError::OutOfMemory)
+        );
+    }
+
+    #[test]
+    fn byte_limit_tracks_live_bytes() {
+        let mut injector = FaultInjector::new(Some(FaultInjection {
+            trigger: FaultInjectionTrigger::ByteLimit(100),
+            error: FaultInjectionError::FailedToMap,
+        }));
+
+        assert_eq!(injector.next_fault(100), None);
+        assert_eq!(
+            injector.next_fault(101),
+            Some(FaultInjectionError::FailedToMap)
+        );
+
+        injector.block_created(60);
+        assert_eq!(injector.next_fault(40), None);
+        assert_eq!(
+            injector.next_fault(41),
+            Some(FaultInjectionError::FailedToMap)
+        );
+
+        injector.block_destroyed(60);
+        assert_eq!(injector.next_fault(100), None);
+    }
+
+    #[test]
+    fn random_is_reproducible() {
+        let trigger = FaultInjectionTrigger::Random {
+            seed: 42,
+            probability: 0.5,
+        };
+
+        let first = faults(&mut injector(trigger), 1000);
+        let second = faults(&mut injector(trigger), 1000);
+        assert_eq!(first, second);
+
+        let other_seed = faults(
+            &mut injector(FaultInjectionTrigger::Random {
+                seed: 43,
+                probability: 0.5,
+            }),
+            1000,
+        );
+        assert_ne!(first, other_seed);
+
+        let failures = first.iter().filter(|&&fail| fail).count();
+        assert!((400..600).contains(&failures), "{failures} failures");
+    }
+
+    #[test]
+    fn random_probability_bounds() {
+        let never = FaultInjectionTrigger::Random {
+            seed: 7,
+            probability: 0.0,
+        };
+        assert!(faults(&mut injector(never), 1000).iter().all(|&fail| !fail));
+
+        let always = FaultInjectionTrigger::Random {
+            seed: 7,
+            probability: 1.0,
+        };
+        assert!(faults(&mut injector(always), 1000).iter().all(|&fail| fail));
+    }
+
+    #[test]
+    fn errors_convert_to_allocation_errors() {
+        assert!(matches!(
+            AllocationError::from(FaultInjectionError::OutOfMemory),
+            AllocationError::OutOfMemory
+        ));
+        assert!(matches!(
+            AllocationError::from(FaultInjectionError::FailedToMap),
+            AllocationError::FailedToMap(_)
+        ));
+    }
+}
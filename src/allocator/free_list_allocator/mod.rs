@@ -23,11 +23,11 @@ use crate::{AllocationError, Result};
 
 const USE_BEST_FIT: bool = true;
 
-fn align_down(val: u64, alignment: u64) -> u64 {
+pub(crate) fn align_down(val: u64, alignment: u64) -> u64 {
     val & !(alignment - 1u64)
 }
 
-fn align_up(val: u64, alignment: u64) -> u64 {
+pub(crate) fn align_up(val: u64, alignment: u64) -> u64 {
     align_down(val + alignment - 1u64, alignment)
 }
 
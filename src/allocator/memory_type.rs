@@ -0,0 +1,1350 @@
+//! Backend-agnostic management of the memory blocks that belong to a single memory type.
+//!
+//! Every backend describes how to create, map and destroy its native memory objects through
+//! [`MemoryDevice`], and shares the logic of finding, growing and releasing blocks through
+//! [`MemoryType`].
+
+#[cfg(feature = "std")]
+use alloc::sync::Arc;
+use alloc::{boxed::Box, vec::Vec};
+use core::{ffi::c_void, num::NonZeroU64, ptr::NonNull};
+#[cfg(feature = "std")]
+use std::backtrace::Backtrace;
+
+use log::Level;
+
+use super::{
+    AllocationType, AllocatorReport, DedicatedBlockAllocator, FaultInjector, FreeListAllocator,
+    MemoryBlockReport, SubAllocator,
+};
+use crate::{AllocationError, AllocationSizes, FaultInjectionError, Result};
+
+/// Wrapper type to only mark a raw pointer [`Send`] + [`Sync`] without having to
+/// mark the entire `Allocation` as such, instead relying on the compiler to
+/// auto-implement this or fail if fields are added that violate this constraint
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SendSyncPtr(pub(crate) NonNull<c_void>);
+// Sending is fine because mapped_ptr does not change based on the thread we are in
+unsafe impl Send for SendSyncPtr {}
+// Sync is also okay because Sending &Allocation is safe: a mutable reference
+// to the data in mapped_ptr is never exposed while `self` is immutably borrowed.
+// In order to break safety guarantees, the user needs to `unsafe`ly dereference
+// `mapped_ptr` themselves.
+unsafe impl Sync for SendSyncPtr {}
+
+/// The operations a backend has to provide to back [`MemoryType`] with real memory.
+pub(crate) trait MemoryDevice {
+    /// Native object backing a single memory block, such as a `VkDeviceMemory` or `ID3D12Heap`.
+    type Memory: Clone;
+    /// Backend-specific description of a memory type, needed to create blocks of that type.
+    type MemoryTypeInfo;
+    /// Backend-specific parameters of a single block.  General blocks are only shared between
+    /// allocations that request equal parameters.
+    type BlockParams: Copy + PartialEq;
+
+    fn create_block(
+        &self,
+        memory_type: &Self::MemoryTypeInfo,
+        memory_type_index: usize,
+        size: u64,
+        params: Self::BlockParams,
+    ) -> Result<Self::Memory>;
+
+    /// Maps a whole block, either persistently right after creating it or on demand.  Returns
+    /// [`None`] for memory that is not accessible from the host.
+    fn map_block(
+        &self,
+        _memory_type: &Self::MemoryTypeInfo,
+        _memory: &Self::Memory,
+    ) -> Result<Option<NonNull<c_void>>> {
+        Ok(None)
+    }
+
+    /// Unmaps a block that was mapped on demand.
+    fn unmap_block(&self, _memory_type: &Self::MemoryTypeInfo, _memory: &Self::Memory) {}
+
+    fn destroy_block(&self, memory_type: &Self::MemoryTypeInfo, memory: Self::Memory, mapped: bool);
+
+    /// Returns [`true`] for memory types whose blocks may never be backed by physical memory, and
+    /// are thus reported separately.
+    fn is_lazily_allocated(_memory_type: &Self::MemoryTypeInfo) -> bool {
+        false
+    }
+
+    /// Returns the number of physical devices a block with `params` is replicated on.
+    fn block_instances(_memory_type: &Self::MemoryTypeInfo, _params: &Self::BlockParams) -> u32 {
+        1
+    }
+
+    /// Returns [`true`] for memory types whose blocks are only accessible to protected resources,
+    /// and are thus reported separately.
+    fn is_protected(_memory_type: &Self::MemoryTypeInfo) -> bool {
+        false
+    }
+}
+
+pub(crate) struct MemoryBlock<D: MemoryDevice> {
+    pub(crate) memory: D::Memory,
+    pub(crate) size: u64,
+    pub(crate) mapped_ptr: Option<SendSyncPtr>,
+    /// Whether [`Self::mapped_ptr`] stays valid for the whole lifetime of the block.
+    persistently_mapped: bool,
+    /// Number of outstanding [`MemoryType::map()`] calls.
+    map_count: u32,
+    /// Whether the memory was imported with [`MemoryType::allocate_imported()`] or adopted with
+    /// [`MemoryType::adopt_block()`] rather than created by this allocator.
+    pub(crate) imported: bool,
+    /// Whether the memory is destroyed together with the block, or left to its creator.
+    owned: bool,
+    pub(crate) sub_allocator: Box<dyn SubAllocator>,
+    pub(crate) params: D::BlockParams,
+    #[cfg(feature = "visualizer")]
+    pub(crate) dedicated_allocation: bool,
+}
+
+/// Describes a single sub-allocation request for [`MemoryType::allocate()`].
+pub(crate) struct SubAllocationDesc<'a, P> {
+    pub(crate) name: &'a str,
+    pub(crate) size: u64,
+    pub(crate) alignment: u64,
+    pub(crate) allocation_type: AllocationType,
+    pub(crate) granularity: u64,
+    /// Places the allocation in a block of its own, regardless of its size.
+    pub(crate) dedicated: bool,
+    pub(crate) block_params: P,
+    #[cfg(feature = "std")]
+    pub(crate) backtrace: Arc<Backtrace>,
+}
+
+/// The result of [`MemoryType::allocate()`], from which backends build their `Allocation`.
+pub(crate) struct SubAllocation<M> {
+    pub(crate) chunk_id: NonZeroU64,
+    pub(crate) offset: u64,
+    pub(crate) memory_block_index: usize,
+    pub(crate) memory: M,
+    /// Mapped pointer with the allocation offset already applied.
+    pub(crate) mapped_ptr: Option<SendSyncPtr>,
+}
+
+pub(crate) struct MemoryType<D: MemoryDevice> {
+    pub(crate) memory_blocks: Vec<Option<MemoryBlock<D>>>,
+    pub(crate) memory_type_index: usize,
+    pub(crate) active_general_blocks: usize,
+    /// Number of blocks in [`Self::memory_blocks`], counted when they are inserted and when
+    /// [`MemoryType::destroy_block()`] destroys them.
+    pub(crate) block_count: usize,
+    /// Whether this memory type uses the host block sizes of [`AllocationSizes`].
+    pub(crate) is_host: bool,
+    /// Whether to leave new blocks unmapped until [`MemoryType::map()`] is called.
+    pub(crate) map_on_demand: bool,
+    pub(crate) info: D::MemoryTypeInfo,
+}
+
+impl<D: MemoryDevice> MemoryType<D> {
+    pub(crate) fn new(memory_type_index: usize, is_host: bool, info: D::MemoryTypeInfo) -> Self {
+        Self {
+            memory_blocks: Vec::default(),
+            memory_type_index,
+            active_general_blocks: 0,
+            block_count: 0,
+            is_host,
+            map_on_demand: false,
+            info,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_block(
+        &self,
+        device: &D,
+        fault_injector: &mut FaultInjector,
+        check_new_block: &mut dyn FnMut(u64) -> Result<()>,
+        size: u64,
+        params: D::BlockParams,
+        dedicated: bool,
+        requires_personal_block: bool,
+    ) -> Result<MemoryBlock<D>> {
+        check_new_block(size)?;
+
+        let injected_fault = fault_injector.next_fault(size);
+        if injected_fault == Some(FaultInjectionError::OutOfMemory) {
+            return Err(FaultInjectionError::OutOfMemory.into());
+        }
+
+        let memory = device.create_block(&self.info, self.memory_type_index, size, params)?;
+
+        let mapped_ptr = if injected_fault == Some(FaultInjectionError::FailedToMap) {
+            Err(FaultInjectionError::FailedToMap.into())
+        } else if self.map_on_demand {
+            Ok(None)
+        } else {
+            device.map_block(&self.info, &memory)
+        };
+        let mapped_ptr = match mapped_ptr {
+            Ok(mapped_ptr) => mapped_ptr.map(SendSyncPtr),
+            Err(e) => {
+                device.destroy_block(&self.info, memory, false);
+                return Err(e);
+            }
+        };
+
+        fault_injector.block_created(size);
+
+        let sub_allocator: Box<dyn SubAllocator> = if dedicated || requires_personal_block {
+            Box::new(DedicatedBlockAllocator::new(size))
+        } else {
+            Box::new(FreeListAllocator::new(size))
+        };
+
+        Ok(MemoryBlock {
+            memory,
+            size,
+            mapped_ptr,
+            persistently_mapped: mapped_ptr.is_some(),
+            map_count: 0,
+            imported: false,
+            owned: true,
+            sub_allocator,
+            params,
+            #[cfg(feature = "visualizer")]
+            dedicated_allocation: dedicated,
+        })
+    }
+
+    fn sub_allocation(
+        block: &MemoryBlock<D>,
+        memory_block_index: usize,
+        offset: u64,
+        chunk_id: NonZeroU64,
+    ) -> SubAllocation<D::Memory> {
+        // On-demand mappings may go away at any time, so never hand those out.
+        let mapped_ptr = block
+            .mapped_ptr
+            .filter(|_| block.persistently_mapped)
+            .and_then(|SendSyncPtr(mapped_ptr)| {
+                let offset_ptr = unsafe { mapped_ptr.as_ptr().add(offset as usize) };
+                NonNull::new(offset_ptr).map(SendSyncPtr)
+            });
+
+        SubAllocation {
+            chunk_id,
+            offset,
+            memory_block_index,
+            memory: block.memory.clone(),
+            mapped_ptr,
+        }
+    }
+
+    /// Sub-allocates `desc` from an existing block, or from a new block if none fits.
+    ///
+    /// `check_new_block` is called with the size of every block that is about to be created, and
+    /// can refuse its creation with an error.
+    pub(crate) fn allocate(
+        &mut self,
+        device: &D,
+        fault_injector: &mut FaultInjector,
+        check_new_block: &mut dyn FnMut(u64) -> Result<()>,
+        desc: &SubAllocationDesc<'_, D::BlockParams>,
+        allocation_sizes: &AllocationSizes,
+    ) -> Result<SubAllocation<D::Memory>> {
+        let memblock_size =
+            allocation_sizes.get_memblock_size(self.is_host, self.active_general_blocks);
+
+        let size = desc.size;
+        let alignment = desc.alignment;
+
+        let requires_personal_block = size > memblock_size;
+
+        // Create a dedicated block for large memory allocations or allocations that require dedicated memory allocations.
+        if desc.dedicated || requires_personal_block {
+            let mem_block = self.create_block(
+                device,
+                fault_injector,
+                check_new_block,
+                size,
+                desc.block_params,
+                desc.dedicated,
+                requires_personal_block,
+            )?;
+
+            let block_index = self.insert_block(mem_block);
+
+            let mem_block = self.memory_blocks[block_index]
+                .as_mut()
+                .ok_or_else(|| AllocationError::Internal("Memory block must be Some".into()))?;
+
+            let (offset, chunk_id) = mem_block.sub_allocator.allocate(
+                size,
+                alignment,
+                desc.allocation_type,
+                desc.granularity,
+                desc.name,
+                #[cfg(feature = "std")]
+                desc.backtrace.clone(),
+            )?;
+
+            return Ok(Self::sub_allocation(
+                mem_block,
+                block_index,
+                offset,
+                chunk_id,
+            ));
+        }
+
+        let mut empty_block_index = None;
+        for (mem_block_i, mem_block) in self.memory_blocks.iter_mut().enumerate().rev() {
+            if let Some(mem_block) = mem_block {
+                if !mem_block.sub_allocator.supports_general_allocations()
+                    || mem_block.params != desc.block_params
+                {
+                    continue;
+                }
+
+                let allocation = mem_block.sub_allocator.allocate(
+                    size,
+                    alignment,
+                    desc.allocation_type,
+                    desc.granularity,
+                    desc.name,
+                    #[cfg(feature = "std")]
+                    desc.backtrace.clone(),
+                );
+
+                match allocation {
+                    Ok((offset, chunk_id)) => {
+                        return Ok(Self::sub_allocation(
+                            mem_block,
+                            mem_block_i,
+                            offset,
+                            chunk_id,
+                        ));
+                    }
+                    Err(AllocationError::OutOfMemory) => {} // Block is full, continue search.
+                    Err(err) => return Err(err),            // Unhandled error, return.
+                }
+            } else if empty_block_index.is_none() {
+                empty_block_index = Some(mem_block_i);
+            }
+        }
+
+        let new_memory_block = self.create_block(
+            device,
+            fault_injector,
+            check_new_block,
+            memblock_size,
+            desc.block_params,
+            false,
+            false,
+        )?;
+
+        self.block_count += 1;
+        let new_block_index = if let Some(block_index) = empty_block_index {
+            self.memory_blocks[block_index] = Some(new_memory_block);
+            block_index
+        } else {
+            self.memory_blocks.push(Some(new_memory_block));
+            self.memory_blocks.len() - 1
+        };
+
+        self.active_general_blocks += 1;
+
+        let mem_block = self.memory_blocks[new_block_index]
+            .as_mut()
+            .ok_or_else(|| AllocationError::Internal("Memory block must be Some".into()))?;
+        let allocation = mem_block.sub_allocator.allocate(
+            size,
+            alignment,
+            desc.allocation_type,
+            desc.granularity,
+            desc.name,
+            #[cfg(feature = "std")]
+            desc.backtrace.clone(),
+        );
+        let (offset, chunk_id) = match allocation {
+            Err(AllocationError::OutOfMemory) => Err(AllocationError::Internal(
+                "Allocation that must succeed failed. This is a bug in the allocator.".into(),
+            )),
+            a => a,
+        }?;
+
+        Ok(Self::sub_allocation(
+            mem_block,
+            new_block_index,
+            offset,
+            chunk_id,
+        ))
+    }
+
+    /// Maps `memory` that was not created by [`MemoryType::allocate()`] like a new block, unless
+    /// blocks are mapped on demand.  If that fails, `memory` is destroyed if `owned`.
+    fn map_foreign_block(
+        &self,
+        device: &D,
+        memory: &D::Memory,
+        owned: bool,
+    ) -> Result<Option<SendSyncPtr>> {
+        let mapped_ptr = if self.map_on_demand {
+            Ok(None)
+        } else {
+            device.map_block(&self.info, memory)
+        };
+        match mapped_ptr {
+            Ok(mapped_ptr) => Ok(mapped_ptr.map(SendSyncPtr)),
+            Err(e) => {
+                if owned {
+                    device.destroy_block(&self.info, memory.clone(), false);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Puts `mem_block` in the first free slot and returns its index.
+    fn insert_block(&mut self, mem_block: MemoryBlock<D>) -> usize {
+        self.block_count += 1;
+        match self.memory_blocks.iter().position(|block| block.is_none()) {
+            Some(i) => {
+                self.memory_blocks[i] = Some(mem_block);
+                i
+            }
+            None => {
+                self.memory_blocks.push(Some(mem_block));
+                self.memory_blocks.len() - 1
+            }
+        }
+    }
+
+    /// Destroys the memory of a block that was removed from [`Self::memory_blocks`], or only
+    /// unmaps it if the block does not own its memory.
+    fn destroy_block(&mut self, device: &D, block: MemoryBlock<D>) {
+        self.block_count -= 1;
+        let mapped = block.mapped_ptr.is_some();
+        if block.owned {
+            device.destroy_block(&self.info, block.memory, mapped);
+        } else if mapped {
+            device.unmap_block(&self.info, &block.memory);
+        }
+    }
+
+    /// Wraps `memory` of `desc.size` bytes that was not created by [`MemoryType::allocate()`],
+    /// such as imported memory, in a dedicated block and allocates all of it.  If `owned`,
+    /// `memory` is destroyed once the allocation is freed or if this fails.
+    #[cfg_attr(not(feature = "vulkan"), allow(dead_code))]
+    pub(crate) fn allocate_imported(
+        &mut self,
+        device: &D,
+        memory: D::Memory,
+        desc: &SubAllocationDesc<'_, D::BlockParams>,
+        owned: bool,
+    ) -> Result<SubAllocation<D::Memory>> {
+        let mut sub_allocator = DedicatedBlockAllocator::new(desc.size);
+        let allocation = sub_allocator.allocate(
+            desc.size,
+            desc.alignment,
+            desc.allocation_type,
+            desc.granularity,
+            desc.name,
+            #[cfg(feature = "std")]
+            desc.backtrace.clone(),
+        );
+        let (offset, chunk_id) = match allocation {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                if owned {
+                    device.destroy_block(&self.info, memory, false);
+                }
+                return Err(e);
+            }
+        };
+
+        let mapped_ptr = self.map_foreign_block(device, &memory, owned)?;
+
+        let block_index = self.insert_block(MemoryBlock {
+            memory,
+            size: desc.size,
+            mapped_ptr,
+            persistently_mapped: mapped_ptr.is_some(),
+            map_count: 0,
+            imported: true,
+            owned,
+            sub_allocator: Box::new(sub_allocator),
+            params: desc.block_params,
+            #[cfg(feature = "visualizer")]
+            dedicated_allocation: true,
+        });
+
+        let mem_block = self.memory_blocks[block_index]
+            .as_ref()
+            .ok_or_else(|| AllocationError::Internal("Memory block must be Some".into()))?;
+
+        Ok(Self::sub_allocation(
+            mem_block,
+            block_index,
+            offset,
+            chunk_id,
+        ))
+    }
+
+    /// Adds `memory` of `size` bytes that was not created by this allocator as a general block,
+    /// which [`MemoryType::allocate()`] sub-allocates from like any other block with equal
+    /// `params`.  The block stays until [`MemoryType::release_block()`], even when empty.  If
+    /// `owned`, `memory` is destroyed once the block is released or if this fails.
+    #[cfg_attr(not(feature = "vulkan"), allow(dead_code))]
+    pub(crate) fn adopt_block(
+        &mut self,
+        device: &D,
+        memory: D::Memory,
+        size: u64,
+        params: D::BlockParams,
+        owned: bool,
+    ) -> Result<usize> {
+        let mapped_ptr = self.map_foreign_block(device, &memory, owned)?;
+
+        Ok(self.insert_block(MemoryBlock {
+            memory,
+            size,
+            mapped_ptr,
+            persistently_mapped: mapped_ptr.is_some(),
+            map_count: 0,
+            imported: true,
+            owned,
+            sub_allocator: Box::new(FreeListAllocator::new(size)),
+            params,
+            #[cfg(feature = "visualizer")]
+            dedicated_allocation: false,
+        }))
+    }
+
+    /// Removes an empty block added with [`MemoryType::adopt_block()`], destroying its memory if
+    /// the block owns it.
+    #[cfg_attr(not(feature = "vulkan"), allow(dead_code))]
+    pub(crate) fn release_block(&mut self, device: &D, memory_block_index: usize) -> Result<()> {
+        let block = self
+            .memory_blocks
+            .get(memory_block_index)
+            .and_then(Option::as_ref)
+            .ok_or_else(|| AllocationError::Internal("Memory block must be Some.".into()))?;
+
+        if !block.imported || !block.sub_allocator.supports_general_allocations() {
+            return Err(AllocationError::MemoryBlockNotAdopted);
+        }
+        if !block.sub_allocator.is_empty() || block.map_count > 0 {
+            return Err(AllocationError::MemoryBlockInUse);
+        }
+
+        if let Some(block) = self.memory_blocks[memory_block_index].take() {
+            self.destroy_block(device, block);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn free(
+        &mut self,
+        device: &D,
+        fault_injector: &mut FaultInjector,
+        memory_block_index: usize,
+        chunk_id: Option<NonZeroU64>,
+    ) -> Result<()> {
+        let mem_block = self.memory_blocks[memory_block_index]
+            .as_mut()
+            .ok_or_else(|| AllocationError::Internal("Memory block must be Some.".into()))?;
+
+        mem_block.sub_allocator.free(chunk_id)?;
+
+        // We only want to destroy this now-empty block if it is either a dedicated/personal
+        // allocation, or a block supporting sub-allocations that is not the last one (ensuring
+        // there's always at least one block/allocator readily available).  Adopted blocks are only
+        // destroyed by `release_block()`.
+        let is_dedicated_or_not_last_general_block =
+            !mem_block.sub_allocator.supports_general_allocations()
+                || (self.active_general_blocks > 1 && !mem_block.imported);
+        if mem_block.sub_allocator.is_empty() && is_dedicated_or_not_last_general_block {
+            let block = self.memory_blocks[memory_block_index]
+                .take()
+                .ok_or_else(|| AllocationError::Internal("Memory block must be Some.".into()))?;
+
+            if block.sub_allocator.supports_general_allocations() {
+                self.active_general_blocks -= 1;
+            }
+
+            if !block.imported {
+                fault_injector.block_destroyed(block.size);
+            }
+            self.destroy_block(device, block);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn rename_allocation(
+        &mut self,
+        memory_block_index: usize,
+        chunk_id: Option<NonZeroU64>,
+        name: &str,
+    ) -> Result<()> {
+        let mem_block = self.memory_blocks[memory_block_index]
+            .as_mut()
+            .ok_or_else(|| AllocationError::Internal("Memory block must be Some.".into()))?;
+
+        mem_block.sub_allocator.rename_allocation(chunk_id, name)
+    }
+
+    pub(crate) fn report_memory_leaks(&self, log_level: Level) {
+        for (block_i, mem_block) in self.memory_blocks.iter().enumerate() {
+            if let Some(mem_block) = mem_block {
+                mem_block.sub_allocator.report_memory_leaks(
+                    log_level,
+                    self.memory_type_index,
+                    block_i,
+                );
+            }
+        }
+    }
+
+    /// Returns the mapped pointer of block `memory_block_index`, mapping it first if needed.
+    /// Every call has to be balanced by a call to [`MemoryType::unmap()`].
+    pub(crate) fn map(&mut self, device: &D, memory_block_index: usize) -> Result<NonNull<c_void>> {
+        let block = self
+            .memory_blocks
+            .get_mut(memory_block_index)
+            .and_then(Option::as_mut)
+            .ok_or_else(|| AllocationError::Internal("Memory block must be Some.".into()))?;
+
+        let SendSyncPtr(mapped_ptr) = match block.mapped_ptr {
+            Some(mapped_ptr) => mapped_ptr,
+            None => {
+                let mapped_ptr = device
+                    .map_block(&self.info, &block.memory)?
+                    .ok_or_else(|| {
+                        AllocationError::FailedToMap("Memory is not host-visible".into())
+                    })?;
+                *block.mapped_ptr.insert(SendSyncPtr(mapped_ptr))
+            }
+        };
+        block.map_count += 1;
+
+        Ok(mapped_ptr)
+    }
+
+    /// Releases a mapping obtained with [`MemoryType::map()`], unmapping the block once nothing
+    /// uses it anymore.
+    pub(crate) fn unmap(&mut self, device: &D, memory_block_index: usize) -> Result<()> {
+        let block = self
+            .memory_blocks
+            .get_mut(memory_block_index)
+            .and_then(Option::as_mut)
+            .ok_or_else(|| AllocationError::Internal("Memory block must be Some.".into()))?;
+
+        block.map_count = block
+            .map_count
+            .checked_sub(1)
+            .ok_or_else(|| AllocationError::Internal("Memory block is not mapped.".into()))?;
+
+        if block.map_count == 0 && !block.persistently_mapped {
+            device.unmap_block(&self.info, &block.memory);
+            block.mapped_ptr = None;
+        }
+
+        Ok(())
+    }
+
+    /// Maps block `memory_block_index` on behalf of a single allocation, which counts its own
+    /// mappings in `map_count`, so that [`MemoryType::unmap_allocation()`] cannot release mappings
+    /// of other allocations in the same block.
+    pub(crate) fn map_allocation(
+        &mut self,
+        device: &D,
+        memory_block_index: usize,
+        map_count: &mut u32,
+    ) -> Result<NonNull<c_void>> {
+        let mapped_ptr = self.map(device, memory_block_index)?;
+        *map_count += 1;
+        Ok(mapped_ptr)
+    }
+
+    /// Releases a mapping obtained with [`MemoryType::map_allocation()`] with the same
+    /// `map_count`.  Fails if that allocation holds no mapping.
+    pub(crate) fn unmap_allocation(
+        &mut self,
+        device: &D,
+        memory_block_index: usize,
+        map_count: &mut u32,
+    ) -> Result<()> {
+        if *map_count == 0 {
+            return Err(AllocationError::FailedToMap(
+                "Allocation was not mapped through `Allocator::map()`".into(),
+            ));
+        }
+        self.unmap(device, memory_block_index)?;
+        *map_count -= 1;
+        Ok(())
+    }
+
+    /// Returns the pointer block `memory_block_index` is currently mapped at, persistently or on
+    /// demand.
+    pub(crate) fn mapped_ptr(&self, memory_block_index: usize) -> Option<NonNull<c_void>> {
+        self.memory_blocks
+            .get(memory_block_index)
+            .and_then(Option::as_ref)
+            .and_then(|block| block.mapped_ptr)
+            .map(|SendSyncPtr(mapped_ptr)| mapped_ptr)
+    }
+
+    /// Returns [`true`] if block `memory_block_index` is currently mapped, persistently or on
+    /// demand.
+    pub(crate) fn is_mapped(&self, memory_block_index: usize) -> bool {
+        self.mapped_ptr(memory_block_index).is_some()
+    }
+
+    /// Returns the size of block `memory_block_index`, in bytes.
+    pub(crate) fn block_size(&self, memory_block_index: usize) -> Option<u64> {
+        self.memory_blocks
+            .get(memory_block_index)
+            .and_then(Option::as_ref)
+            .map(|block| block.size)
+    }
+
+    /// Destroys all remaining memory blocks, regardless of live allocations.
+    pub(crate) fn destroy_blocks(&mut self, device: &D) {
+        for memory_block_index in 0..self.memory_blocks.len() {
+            if let Some(block) = self.memory_blocks[memory_block_index].take() {
+                self.destroy_block(device, block);
+            }
+        }
+        self.active_general_blocks = 0;
+    }
+
+    /// Current total capacity of the memory blocks of this memory type, in bytes
+    pub(crate) fn capacity(&self) -> u64 {
+        self.memory_blocks
+            .iter()
+            .flatten()
+            .map(|block| block.size)
+            .sum()
+    }
+}
+
+pub(crate) fn generate_report<'a, D: MemoryDevice + 'a>(
+    memory_types: impl IntoIterator<Item = &'a MemoryType<D>>,
+) -> AllocatorReport {
+    let mut allocations = vec![];
+    let mut blocks = vec![];
+    let mut total_allocated_bytes = 0;
+    let mut total_capacity_bytes = 0;
+    let mut total_lazily_allocated_bytes = 0;
+    let mut total_imported_bytes = 0;
+    let mut total_protected_bytes = 0;
+
+    for memory_type in memory_types {
+        let lazily_allocated = D::is_lazily_allocated(&memory_type.info);
+        let protected = D::is_protected(&memory_type.info);
+        for block in memory_type.memory_blocks.iter().flatten() {
+            let first_allocation = allocations.len();
+            allocations.extend(block.sub_allocator.report_allocations());
+            let instances = D::block_instances(&memory_type.info, &block.params);
+            let size = block.size * u64::from(instances);
+            let allocated_bytes = allocations[first_allocation..]
+                .iter()
+                .map(|report| report.size)
+                .sum::<u64>()
+                * u64::from(instances);
+            if block.imported {
+                total_imported_bytes += size;
+                // Adopted general blocks are sub-allocated from like any other block
+                if block.sub_allocator.supports_general_allocations() {
+                    total_allocated_bytes += allocated_bytes;
+                    total_capacity_bytes += size;
+                }
+            } else if lazily_allocated {
+                total_lazily_allocated_bytes += size;
+            } else {
+                total_allocated_bytes += allocated_bytes;
+                total_capacity_bytes += size;
+                if protected {
+                    total_protected_bytes += size;
+                }
+            }
+            blocks.push(MemoryBlockReport {
+                size: block.size,
+                allocations: first_allocation..allocations.len(),
+                lazily_allocated,
+                imported: block.imported,
+                protected,
+                instances,
+            });
+        }
+    }
+
+    AllocatorReport {
+        allocations,
+        blocks,
+        total_allocated_bytes,
+        total_capacity_bytes,
+        total_lazily_allocated_bytes,
+        total_imported_bytes,
+        total_protected_bytes,
+        budget_groups: Vec::new(),
+        fallback_allocations: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use core::cell::{Cell, RefCell};
+
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    struct FakeMemoryTypeInfo {
+        host_visible: bool,
+        lazily_allocated: bool,
+        protected: bool,
+    }
+
+    impl FakeMemoryTypeInfo {
+        fn device_local() -> Self {
+            Self {
+                host_visible: false,
+                lazily_allocated: false,
+                protected: false,
+            }
+        }
+
+        fn host_visible() -> Self {
+            Self {
+                host_visible: true,
+                ..Self::device_local()
+            }
+        }
+    }
+
+    /// Hands out indices into a list of host buffers as its native memory objects, and records
+    /// every call made by [`MemoryType`].
+    struct FakeDevice {
+        memory: RefCell<Vec<Option<Box<[u8]>>>>,
+        map_calls: Cell<u32>,
+        unmap_calls: Cell<u32>,
+        destroy_calls: Cell<u32>,
+        /// Makes every [`MemoryDevice::map_block()`] call fail.
+        fail_map: Cell<bool>,
+        /// Shared by all calls, as by a real allocator, so that its byte counts stay balanced.
+        fault_injector: RefCell<FaultInjector>,
+    }
+
+    impl Default for FakeDevice {
+        fn default() -> Self {
+            Self {
+                memory: RefCell::default(),
+                map_calls: Cell::default(),
+                unmap_calls: Cell::default(),
+                destroy_calls: Cell::default(),
+                fail_map: Cell::default(),
+                fault_injector: RefCell::new(FaultInjector::new(None)),
+            }
+        }
+    }
+
+    impl FakeDevice {
+        fn live_blocks(&self) -> usize {
+            self.memory.borrow().iter().flatten().count()
+        }
+    }
+
+    impl MemoryDevice for FakeDevice {
+        type Memory = usize;
+        type MemoryTypeInfo = FakeMemoryTypeInfo;
+        /// Number of instances of the block, as for device groups.
+        type BlockParams = u32;
+
+        fn create_block(
+            &self,
+            _memory_type: &FakeMemoryTypeInfo,
+            _memory_type_index: usize,
+            size: u64,
+            _params: u32,
+        ) -> Result<usize> {
+            let mut memory = self.memory.borrow_mut();
+            memory.push(Some(vec![0; size as usize].into_boxed_slice()));
+            Ok(memory.len() - 1)
+        }
+
+        fn map_block(
+            &self,
+            memory_type: &FakeMemoryTypeInfo,
+            memory: &usize,
+        ) -> Result<Option<NonNull<c_void>>> {
+            if !memory_type.host_visible {
+                return Ok(None);
+            }
+            if self.fail_map.get() {
+                return Err(AllocationError::FailedToMap("Injected by the test".into()));
+            }
+            self.map_calls.set(self.map_calls.get() + 1);
+            let mut buffers = self.memory.borrow_mut();
+            let buffer = buffers[*memory]
+                .as_mut()
+                .expect("Mapping a destroyed block");
+            Ok(NonNull::new(buffer.as_mut_ptr().cast()))
+        }
+
+        fn unmap_block(&self, _memory_type: &FakeMemoryTypeInfo, _memory: &usize) {
+            self.unmap_calls.set(self.unmap_calls.get() + 1);
+        }
+
+        fn destroy_block(&self, _memory_type: &FakeMemoryTypeInfo, memory: usize, mapped: bool) {
+            if mapped {
+                self.unmap_calls.set(self.unmap_calls.get() + 1);
+            }
+            self.destroy_calls.set(self.destroy_calls.get() + 1);
+            let buffer = self.memory.borrow_mut()[memory].take();
+            assert!(buffer.is_some(), "Destroying block {memory} twice");
+        }
+
+        fn is_lazily_allocated(memory_type: &FakeMemoryTypeInfo) -> bool {
+            memory_type.lazily_allocated
+        }
+
+        fn block_instances(_memory_type: &FakeMemoryTypeInfo, params: &u32) -> u32 {
+            *params
+        }
+
+        fn is_protected(memory_type: &FakeMemoryTypeInfo) -> bool {
+            memory_type.protected
+        }
+    }
+
+    fn desc(size: u64, dedicated: bool) -> SubAllocationDesc<'static, u32> {
+        SubAllocationDesc {
+            name: "test",
+            size,
+            alignment: 256,
+            allocation_type: AllocationType::Linear,
+            granularity: 1,
+            dedicated,
+            block_params: 1,
+            #[cfg(feature = "std")]
+            backtrace: Arc::new(Backtrace::disabled()),
+        }
+    }
+
+    fn allocate(
+        memory_type: &mut MemoryType<FakeDevice>,
+        device: &FakeDevice,
+        desc: &SubAllocationDesc<'_, u32>,
+    ) -> Result<SubAllocation<usize>> {
+        memory_type.allocate(
+            device,
+            &mut device.fault_injector.borrow_mut(),
+            &mut |_| Ok(()),
+            desc,
+            &AllocationSizes::new(4 * MB, 4 * MB),
+        )
+    }
+
+    fn free(
+        memory_type: &mut MemoryType<FakeDevice>,
+        device: &FakeDevice,
+        allocation: &SubAllocation<usize>,
+    ) -> Result<()> {
+        memory_type.free(
+            device,
+            &mut device.fault_injector.borrow_mut(),
+            allocation.memory_block_index,
+            Some(allocation.chunk_id),
+        )
+    }
+
+    #[test]
+    fn small_allocations_share_a_general_block() {
+        let device = FakeDevice::default();
+        let mut memory_type = MemoryType::new(0, false, FakeMemoryTypeInfo::device_local());
+
+        let a = allocate(&mut memory_type, &device, &desc(1024, false)).unwrap();
+        let b = allocate(&mut memory_type, &device, &desc(1024, false)).unwrap();
+
+        assert_eq!(a.memory_block_index, b.memory_block_index);
+        assert_ne!(a.offset, b.offset);
+        assert_eq!(b.offset % 256, 0);
+        assert_eq!(memory_type.active_general_blocks, 1);
+        assert_eq!(memory_type.capacity(), 4 * MB);
+        assert!(a.mapped_ptr.is_none());
+
+        free(&mut memory_type, &device, &a).unwrap();
+        free(&mut memory_type, &device, &b).unwrap();
+
+        // The last general block is kept around for future allocations.
+        assert_eq!(device.live_blocks(), 1);
+        assert_eq!(memory_type.active_general_blocks, 1);
+    }
+
+    #[test]
+    fn block_params_separate_general_blocks() {
+        let device = FakeDevice::default();
+        let mut memory_type = MemoryType::new(0, false, FakeMemoryTypeInfo::device_local());
+
+        let a = allocate(&mut memory_type, &device, &desc(1024, false)).unwrap();
+        let b = allocate(
+            &mut memory_type,
+            &device,
+            &SubAllocationDesc {
+                block_params: 2,
+                ..desc(1024, false)
+            },
+        )
+        .unwrap();
+
+        assert_ne!(a.memory_block_index, b.memory_block_index);
+        assert_eq!(memory_type.active_general_blocks, 2);
+    }
+
+    #[test]
+    fn empty_general_blocks_are_destroyed_except_the_last() {
+        let device = FakeDevice::default();
+        let mut memory_type = MemoryType::new(0, false, FakeMemoryTypeInfo::device_local());
+
+        let a = allocate(&mut memory_type, &device, &desc(3 * MB, false)).unwrap();
+        let b = allocate(&mut memory_type, &device, &desc(3 * MB, false)).unwrap();
+        assert_ne!(a.memory_block_index, b.memory_block_index);
+        assert_eq!(memory_type.active_general_blocks, 2);
+
+        free(&mut memory_type, &device, &a).unwrap();
+        assert_eq!(device.destroy_calls.get(), 1);
+        assert_eq!(memory_type.active_general_blocks, 1);
+
+        free(&mut memory_type, &device, &b).unwrap();
+        assert_eq!(device.destroy_calls.get(), 1);
+
+        // The freed slot is reused for the next block.
+        let c = allocate(&mut memory_type, &device, &desc(3 * MB, false)).unwrap();
+        let d = allocate(&mut memory_type, &device, &desc(3 * MB, false)).unwrap();
+        assert_eq!(c.memory_block_index, b.memory_block_index);
+        assert_eq!(d.memory_block_index, a.memory_block_index);
+    }
+
+    #[test]
+    fn dedicated_and_oversized_allocations_get_their_own_block() {
+        let device = FakeDevice::default();
+        let mut memory_type = MemoryType::new(0, false, FakeMemoryTypeInfo::device_local());
+
+        let dedicated = allocate(&mut memory_type, &device, &desc(1024, true)).unwrap();
+        let oversized = allocate(&mut memory_type, &device, &desc(8 * MB, false)).unwrap();
+        let general = allocate(&mut memory_type, &device, &desc(1024, false)).unwrap();
+
+        assert_eq!(dedicated.offset, 0);
+        assert_eq!(oversized.offset, 0);
+        assert_ne!(dedicated.memory_block_index, oversized.memory_block_index);
+        assert_ne!(general.memory_block_index, dedicated.memory_block_index);
+        assert_ne!(general.memory_block_index, oversized.memory_block_index);
+        assert_eq!(memory_type.active_general_blocks, 1);
+        assert_eq!(memory_type.capacity(), 1024 + 8 * MB + 4 * MB);
+
+        free(&mut memory_type, &device, &dedicated).unwrap();
+        free(&mut memory_type, &device, &oversized).unwrap();
+        assert_eq!(device.destroy_calls.get(), 2);
+        assert_eq!(device.live_blocks(), 1);
+    }
+
+    #[test]
+    fn check_new_block_can_refuse_blocks() {
+        let device = FakeDevice::default();
+        let mut memory_type = MemoryType::new(0, false, FakeMemoryTypeInfo::device_local());
+
+        let result = memory_type.allocate(
+            &device,
+            &mut FaultInjector::new(None),
+            &mut |size| {
+                assert_eq!(size, 4 * MB);
+                Err(AllocationError::OutOfMemory)
+            },
+            &desc(1024, false),
+            &AllocationSizes::new(4 * MB, 4 * MB),
+        );
+
+        assert!(matches!(result, Err(AllocationError::OutOfMemory)));
+        assert_eq!(device.live_blocks(), 0);
+        assert_eq!(memory_type.active_general_blocks, 0);
+    }
+
+    #[test]
+    fn persistently_mapped_blocks_offset_the_mapped_pointer() {
+        let device = FakeDevice::default();
+        let mut memory_type = MemoryType::new(0, true, FakeMemoryTypeInfo::host_visible());
+
+        let a = allocate(&mut memory_type, &device, &desc(1024, false)).unwrap();
+        let b = allocate(&mut memory_type, &device, &desc(1024, false)).unwrap();
+
+        let SendSyncPtr(a_ptr) = a.mapped_ptr.unwrap();
+        let SendSyncPtr(b_ptr) = b.mapped_ptr.unwrap();
+        assert_eq!(
+            b_ptr.as_ptr() as usize - a_ptr.as_ptr() as usize,
+            (b.offset - a.offset) as usize
+        );
+        assert_eq!(device.map_calls.get(), 1);
+
+        // Mapping a persistently mapped block never unmaps it.
+        let mapped = memory_type.map(&device, a.memory_block_index).unwrap();
+        assert_eq!(mapped.as_ptr(), a_ptr.as_ptr());
+        memory_type.unmap(&device, a.memory_block_index).unwrap();
+        assert_eq!(device.map_calls.get(), 1);
+        assert_eq!(device.unmap_calls.get(), 0);
+    }
+
+    #[test]
+    fn on_demand_mappings_are_reference_counted() {
+        let device = FakeDevice::default();
+        let mut memory_type = MemoryType::new(0, true, FakeMemoryTypeInfo::host_visible());
+        memory_type.map_on_demand = true;
+
+        let a = allocate(&mut memory_type, &device, &desc(1024, false)).unwrap();
+        assert!(a.mapped_ptr.is_none());
+        assert!(!memory_type.is_mapped(a.memory_block_index));
+        assert_eq!(device.map_calls.get(), 0);
+
+        let first = memory_type.map(&device, a.memory_block_index).unwrap();
+        let second = memory_type.map(&device, a.memory_block_index).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(device.map_calls.get(), 1);
+
+        // New sub-allocations never see the transient mapping.
+        let b = allocate(&mut memory_type, &device, &desc(1024, false)).unwrap();
+        assert!(b.mapped_ptr.is_none());
+
+        memory_type.unmap(&device, a.memory_block_index).unwrap();
+        assert_eq!(device.unmap_calls.get(), 0);
+        assert!(memory_type.is_mapped(a.memory_block_index));
+        memory_type.unmap(&device, a.memory_block_index).unwrap();
+        assert_eq!(device.unmap_calls.get(), 1);
+        assert!(!memory_type.is_mapped(a.memory_block_index));
+
+        assert!(memory_type.unmap(&device, a.memory_block_index).is_err());
+
+        // Mapping again after the block was unmapped maps it anew.
+        memory_type.map(&device, a.memory_block_index).unwrap();
+        assert_eq!(device.map_calls.get(), 2);
+    }
+
+    #[test]
+    fn allocations_only_release_their_own_mappings() {
+        let device = FakeDevice::default();
+        let mut memory_type = MemoryType::new(0, true, FakeMemoryTypeInfo::host_visible());
+        memory_type.map_on_demand = true;
+
+        let a = allocate(&mut memory_type, &device, &desc(1024, false)).unwrap();
+        let b = allocate(&mut memory_type, &device, &desc(1024, false)).unwrap();
+        assert_eq!(a.memory_block_index, b.memory_block_index);
+        let (mut a_map_count, mut b_map_count) = (0, 0);
+
+        memory_type
+            .map_allocation(&device, a.memory_block_index, &mut a_map_count)
+            .unwrap();
+        assert_eq!(a_map_count, 1);
+
+        // `b` never mapped the block, so it cannot unmap it from under `a`.
+        assert!(matches!(
+            memory_type.unmap_allocation(&device, b.memory_block_index, &mut b_map_count),
+            Err(AllocationError::FailedToMap(_))
+        ));
+        assert!(memory_type.is_mapped(a.memory_block_index));
+        assert_eq!(device.unmap_calls.get(), 0);
+
+        memory_type
+            .map_allocation(&device, b.memory_block_index, &mut b_map_count)
+            .unwrap();
+        memory_type
+            .unmap_allocation(&device, b.memory_block_index, &mut b_map_count)
+            .unwrap();
+        assert_eq!(b_map_count, 0);
+        assert!(memory_type.is_mapped(a.memory_block_index));
+
+        memory_type
+            .unmap_allocation(&device, a.memory_block_index, &mut a_map_count)
+            .unwrap();
+        assert_eq!(a_map_count, 0);
+        assert!(!memory_type.is_mapped(a.memory_block_index));
+        assert_eq!(device.unmap_calls.get(), 1);
+    }
+
+    #[test]
+    fn mapping_device_local_memory_fails() {
+        let device = FakeDevice::default();
+        let mut memory_type = MemoryType::new(0, false, FakeMemoryTypeInfo::device_local());
+
+        let a = allocate(&mut memory_type, &device, &desc(1024, false)).unwrap();
+
+        assert!(matches!(
+            memory_type.map(&device, a.memory_block_index),
+            Err(AllocationError::FailedToMap(_))
+        ));
+        assert!(memory_type.unmap(&device, a.memory_block_index).is_err());
+    }
+
+    #[test]
+    fn owned_imported_memory_is_destroyed_with_its_allocation() {
+        let device = FakeDevice::default();
+        let mut memory_type = MemoryType::new(0, true, FakeMemoryTypeInfo::host_visible());
+
+        let memory = device.create_block(&memory_type.info, 0, 1024, 1).unwrap();
+        let a = memory_type
+            .allocate_imported(&device, memory, &desc(1024, true), true)
+            .unwrap();
+        assert!(a.mapped_ptr.is_some());
+
+        free(&mut memory_type, &device, &a).unwrap();
+        assert_eq!(device.live_blocks(), 0);
+        assert_eq!(device.unmap_calls.get(), 1);
+    }
+
+    #[test]
+    fn borrowed_imported_memory_is_left_alive() {
+        let device = FakeDevice::default();
+        let mut memory_type = MemoryType::new(0, true, FakeMemoryTypeInfo::host_visible());
+
+        let memory = device.create_block(&memory_type.info, 0, 1024, 1).unwrap();
+        let a = memory_type
+            .allocate_imported(&device, memory, &desc(1024, true), false)
+            .unwrap();
+
+        free(&mut memory_type, &device, &a).unwrap();
+        assert_eq!(device.live_blocks(), 1);
+        assert_eq!(device.destroy_calls.get(), 0);
+        // The mapping made by the allocator is still released.
+        assert_eq!(device.unmap_calls.get(), 1);
+    }
+
+    #[test]
+    fn failed_imports_only_destroy_owned_memory() {
+        let device = FakeDevice::default();
+        let mut memory_type = MemoryType::new(0, true, FakeMemoryTypeInfo::host_visible());
+        device.fail_map.set(true);
+
+        let memory = device.create_block(&memory_type.info, 0, 1024, 1).unwrap();
+        let result = memory_type.allocate_imported(&device, memory, &desc(1024, true), false);
+        assert!(matches!(result, Err(AllocationError::FailedToMap(_))));
+        assert_eq!(device.live_blocks(), 1);
+
+        let memory = device.create_block(&memory_type.info, 0, 1024, 1).unwrap();
+        let result = memory_type.allocate_imported(&device, memory, &desc(1024, true), true);
+        assert!(matches!(result, Err(AllocationError::FailedToMap(_))));
+        assert_eq!(device.live_blocks(), 1);
+        assert_eq!(device.destroy_calls.get(), 1);
+        assert_eq!(memory_type.capacity(), 0);
+    }
+
+    #[test]
+    fn adopted_blocks_are_sub_allocated_and_reported() {
+        let device = FakeDevice::default();
+        let mut memory_type = MemoryType::new(0, false, FakeMemoryTypeInfo::device_local());
+
+        let memory = device.create_block(&memory_type.info, 0, MB, 1).unwrap();
+        let block = memory_type
+            .adopt_block(&device, memory, MB, 1, true)
+            .unwrap();
+        let a = allocate(&mut memory_type, &device, &desc(1024, false)).unwrap();
+        assert_eq!(a.memory_block_index, block);
+
+        let report = generate_report([&memory_type]);
+        assert_eq!(report.total_allocated_bytes, 1024);
+        assert_eq!(report.total_capacity_bytes, MB);
+        assert_eq!(report.total_imported_bytes, MB);
+    }
+
+    #[test]
+    fn release_block_checks_the_block() {
+        let device = FakeDevice::default();
+        let mut memory_type = MemoryType::new(0, false, FakeMemoryTypeInfo::device_local());
+
+        let memory = device.create_block(&memory_type.info, 0, MB, 1).unwrap();
+        let block = memory_type
+            .adopt_block(&device, memory, MB, 1, false)
+            .unwrap();
+        let a = allocate(&mut memory_type, &device, &desc(1024, false)).unwrap();
+        assert_eq!(a.memory_block_index, block);
+        assert!(matches!(
+            memory_type.release_block(&device, block),
+            Err(AllocationError::MemoryBlockInUse)
+        ));
+
+        let dedicated = allocate(&mut memory_type, &device, &desc(1024, true)).unwrap();
+        assert!(matches!(
+            memory_type.release_block(&device, dedicated.memory_block_index),
+            Err(AllocationError::MemoryBlockNotAdopted)
+        ));
+
+        free(&mut memory_type, &device, &a).unwrap();
+        memory_type.release_block(&device, block).unwrap();
+        // Memory that the block does not own is handed back instead of destroyed.
+        assert_eq!(device.destroy_calls.get(), 0);
+        assert_eq!(memory_type.capacity(), 1024);
+    }
+
+    #[test]
+    fn destroy_blocks_releases_everything() {
+        let device = FakeDevice::default();
+        let mut memory_type = MemoryType::new(0, true, FakeMemoryTypeInfo::host_visible());
+
+        allocate(&mut memory_type, &device, &desc(1024, false)).unwrap();
+        allocate(&mut memory_type, &device, &desc(1024, true)).unwrap();
+        memory_type.destroy_blocks(&device);
+
+        assert_eq!(device.live_blocks(), 0);
+        assert_eq!(memory_type.active_general_blocks, 0);
+        assert_eq!(memory_type.block_count, 0);
+        assert_eq!(memory_type.capacity(), 0);
+    }
+
+    #[test]
+    fn report_totals() {
+        let device = FakeDevice::default();
+        let mut regular = MemoryType::new(0, false, FakeMemoryTypeInfo::device_local());
+        let mut lazy = MemoryType::new(
+            1,
+            false,
+            FakeMemoryTypeInfo {
+                lazily_allocated: true,
+                ..FakeMemoryTypeInfo::device_local()
+            },
+        );
+        let mut protected = MemoryType::new(
+            2,
+            false,
+            FakeMemoryTypeInfo {
+                protected: true,
+                ..FakeMemoryTypeInfo::device_local()
+            },
+        );
+
+        allocate(&mut regular, &device, &desc(1024, false)).unwrap();
+        allocate(&mut regular, &device, &desc(2048, true)).unwrap();
+        allocate(
+            &mut regular,
+            &device,
+            &SubAllocationDesc {
+                block_params: 2,
+                ..desc(512, true)
+            },
+        )
+        .unwrap();
+        allocate(&mut lazy, &device, &desc(1024, true)).unwrap();
+        allocate(&mut protected, &device, &desc(4096, true)).unwrap();
+
+        let report = generate_report([&regular, &lazy, &protected]);
+
+        assert_eq!(report.allocations.len(), 5);
+        assert_eq!(report.blocks.len(), 5);
+        assert_eq!(
+            regular.block_count + lazy.block_count + protected.block_count,
+            5
+        );
+        assert_eq!(report.total_allocated_bytes, 1024 + 2048 + 2 * 512 + 4096);
+        assert_eq!(report.total_capacity_bytes, 4 * MB + 2048 + 2 * 512 + 4096);
+        assert_eq!(report.total_lazily_allocated_bytes, 1024);
+        assert_eq!(report.total_imported_bytes, 0);
+        assert_eq!(report.total_protected_bytes, 4096);
+        assert!(report.blocks[4].protected);
+        assert_eq!(report.blocks[2].instances, 2);
+        assert_eq!(report.blocks[2].size, 512);
+    }
+}
@@ -12,8 +12,14 @@ use crate::result::*;
 pub(crate) mod dedicated_block_allocator;
 pub(crate) use dedicated_block_allocator::DedicatedBlockAllocator;
 
+pub(crate) mod fault_injector;
+pub(crate) use fault_injector::FaultInjector;
+
 pub(crate) mod free_list_allocator;
-pub(crate) use free_list_allocator::FreeListAllocator;
+pub(crate) use free_list_allocator::{align_down, align_up, FreeListAllocator};
+
+pub(crate) mod memory_type;
+pub(crate) use memory_type::{MemoryDevice, MemoryType, SendSyncPtr, SubAllocationDesc};
 
 #[derive(PartialEq, Copy, Clone, Debug)]
 #[repr(u8)]
@@ -55,6 +61,30 @@ pub struct MemoryBlockReport {
     /// The range of allocations in [`AllocatorReport::allocations`] that are associated
     /// to this memory block.
     pub allocations: Range<usize>,
+    /// Whether this memory block may never be backed by physical memory, see
+    /// [`AllocatorReport::total_lazily_allocated_bytes`].
+    pub lazily_allocated: bool,
+    /// Whether this memory block was imported or adopted from outside of the allocator, see
+    /// [`AllocatorReport::total_imported_bytes`].
+    pub imported: bool,
+    /// Whether this memory block is only accessible to protected resources, see
+    /// [`AllocatorReport::total_protected_bytes`].
+    pub protected: bool,
+    /// Number of physical devices this memory block is replicated on, for memory blocks in a
+    /// `MULTI_INSTANCE` heap of a Vulkan device group.  Every instance is counted in the totals
+    /// of the [`AllocatorReport`], while [`Self::size`] is the size of a single instance.
+    pub instances: u32,
+}
+
+/// Describes a budget group in the [`AllocatorReport`].
+#[derive(Clone, Debug)]
+pub struct BudgetGroupReport {
+    /// The name provided when creating the budget group.
+    pub name: String,
+    /// The maximum number of bytes that can be allocated in this budget group.
+    pub limit: u64,
+    /// Sum of the memory used by all live allocations in this budget group, in bytes.
+    pub allocated_bytes: u64,
 }
 
 /// A report that can be generated for informational purposes using `Allocator::generate_report()`.
@@ -68,6 +98,23 @@ pub struct AllocatorReport {
     pub total_allocated_bytes: u64,
     /// Sum of the memory capacity of all memory blocks including unallocated regions, in bytes.
     pub total_capacity_bytes: u64,
+    /// Sum of the memory capacity of all lazily allocated memory blocks, in bytes.  These are
+    /// excluded from [`Self::total_allocated_bytes`] and [`Self::total_capacity_bytes`] since
+    /// their committed size is not real memory use.
+    pub total_lazily_allocated_bytes: u64,
+    /// Sum of the sizes of all imported and adopted memory blocks, in bytes.  Imported memory is
+    /// excluded from [`Self::total_allocated_bytes`] and [`Self::total_capacity_bytes`] since it
+    /// was not allocated by the allocator, while blocks adopted for sub-allocation are counted
+    /// there like any other block.
+    pub total_imported_bytes: u64,
+    /// Sum of the memory capacity of all protected memory blocks, in bytes.  This is the part of
+    /// [`Self::total_capacity_bytes`] that only protected resources can use.
+    pub total_protected_bytes: u64,
+    /// All budget groups.  Only the Vulkan allocator supports budget groups.
+    pub budget_groups: Vec<BudgetGroupReport>,
+    /// Number of allocations that had to fall back to a less preferred memory type since the
+    /// allocator was created.  Only the Vulkan allocator supports fallbacks for every location.
+    pub fallback_allocations: u64,
 }
 
 impl fmt::Debug for AllocationReport {
@@ -98,9 +145,23 @@ impl fmt::Debug for AllocatorReport {
                     fmt_bytes(self.total_capacity_bytes)
                 ),
             )
+            .field(
+                "lazily_allocated",
+                &core::format_args!("{}", fmt_bytes(self.total_lazily_allocated_bytes)),
+            )
+            .field(
+                "imported",
+                &core::format_args!("{}", fmt_bytes(self.total_imported_bytes)),
+            )
+            .field(
+                "protected",
+                &core::format_args!("{}", fmt_bytes(self.total_protected_bytes)),
+            )
             .field("blocks", &self.blocks.len())
             .field("allocations", &self.allocations.len())
             .field("largest", &allocations.as_slice())
+            .field("budget_groups", &self.budget_groups)
+            .field("fallback_allocations", &self.fallback_allocations)
             .finish()
     }
 }
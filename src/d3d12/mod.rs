@@ -25,8 +25,7 @@ pub use visualizer::AllocatorVisualizer;
 
 use crate::{
     allocator::{
-        AllocationType, AllocatorReport, DedicatedBlockAllocator, FreeListAllocator,
-        MemoryBlockReport, SubAllocator,
+        self, AllocationType, AllocatorReport, FaultInjector, MemoryDevice, SubAllocationDesc,
     },
     AllocationError, AllocationSizes, AllocatorDebugSettings, MemoryLocation, Result,
 };
@@ -258,247 +257,57 @@ impl Allocation {
     }
 }
 
+/// D3D12-specific description of a memory type.
 #[derive(Debug)]
-struct MemoryBlock {
-    heap: ID3D12Heap,
-    size: u64,
-    sub_allocator: Box<dyn SubAllocator>,
-}
-impl MemoryBlock {
-    fn new(
-        device: &ID3D12Device,
-        size: u64,
-        heap_properties: &D3D12_HEAP_PROPERTIES,
-        heap_category: HeapCategory,
-        dedicated: bool,
-    ) -> Result<Self> {
-        let heap = {
-            let mut desc = D3D12_HEAP_DESC {
-                SizeInBytes: size,
-                Properties: *heap_properties,
-                Alignment: D3D12_DEFAULT_MSAA_RESOURCE_PLACEMENT_ALIGNMENT as u64,
-                ..Default::default()
-            };
-            desc.Flags = match heap_category {
-                HeapCategory::All => D3D12_HEAP_FLAG_NONE,
-                HeapCategory::Buffer => D3D12_HEAP_FLAG_ALLOW_ONLY_BUFFERS,
-                HeapCategory::RtvDsvTexture => D3D12_HEAP_FLAG_ALLOW_ONLY_RT_DS_TEXTURES,
-                HeapCategory::OtherTexture => D3D12_HEAP_FLAG_ALLOW_ONLY_NON_RT_DS_TEXTURES,
-            };
-
-            let mut heap = None;
-            let hr = unsafe { device.CreateHeap(&desc, &mut heap) };
-            match hr {
-                Err(e) if e.code() == E_OUTOFMEMORY => Err(AllocationError::OutOfMemory),
-                Err(e) => Err(AllocationError::Internal(format!(
-                    "ID3D12Device::CreateHeap failed: {e}"
-                ))),
-                Ok(()) => heap.ok_or_else(|| {
-                    AllocationError::Internal(
-                        "ID3D12Heap pointer is null, but should not be.".into(),
-                    )
-                }),
-            }?
-        };
-
-        let sub_allocator: Box<dyn SubAllocator> = if dedicated {
-            Box::new(DedicatedBlockAllocator::new(size))
-        } else {
-            Box::new(FreeListAllocator::new(size))
-        };
-
-        Ok(Self {
-            heap,
-            size,
-            sub_allocator,
-        })
-    }
-}
-
-#[derive(Debug)]
-struct MemoryType {
-    memory_blocks: Vec<Option<MemoryBlock>>,
-    committed_allocations: CommittedAllocationStatistics,
+pub(crate) struct MemoryTypeInfo {
     memory_location: MemoryLocation,
     heap_category: HeapCategory,
     heap_properties: D3D12_HEAP_PROPERTIES,
-    memory_type_index: usize,
-    active_general_blocks: usize,
+    committed_allocations: CommittedAllocationStatistics,
 }
 
-impl MemoryType {
-    fn allocate(
-        &mut self,
-        device: &ID3D12DeviceVersion,
-        desc: &AllocationCreateDesc<'_>,
-        #[cfg(feature = "std")] backtrace: Arc<Backtrace>,
-        allocation_sizes: &AllocationSizes,
-    ) -> Result<Allocation> {
-        let allocation_type = AllocationType::Linear;
-
-        let is_host = self.heap_properties.Type != D3D12_HEAP_TYPE_DEFAULT;
-        let memblock_size = allocation_sizes.get_memblock_size(is_host, self.active_general_blocks);
-
-        let size = desc.size;
-        let alignment = desc.alignment;
-
-        // Create a dedicated block for large memory allocations
-        if size > memblock_size {
-            let mem_block = MemoryBlock::new(
-                device,
-                size,
-                &self.heap_properties,
-                self.heap_category,
-                true,
-            )?;
-
-            let block_index = self.memory_blocks.iter().position(|block| block.is_none());
-            let block_index = match block_index {
-                Some(i) => {
-                    self.memory_blocks[i].replace(mem_block);
-                    i
-                }
-                None => {
-                    self.memory_blocks.push(Some(mem_block));
-                    self.memory_blocks.len() - 1
-                }
-            };
-
-            let mem_block = self.memory_blocks[block_index]
-                .as_mut()
-                .ok_or_else(|| AllocationError::Internal("Memory block must be Some".into()))?;
+type MemoryType = allocator::MemoryType<ID3D12DeviceVersion>;
 
-            let (offset, chunk_id) = mem_block.sub_allocator.allocate(
-                size,
-                alignment,
-                allocation_type,
-                1,
-                desc.name,
-                #[cfg(feature = "std")]
-                backtrace,
-            )?;
-
-            return Ok(Allocation {
-                chunk_id: Some(chunk_id),
-                size,
-                offset,
-                memory_block_index: block_index,
-                memory_type_index: self.memory_type_index,
-                heap: mem_block.heap.clone(),
-                name: Some(desc.name.into()),
-            });
-        }
+impl MemoryDevice for ID3D12DeviceVersion {
+    type Memory = ID3D12Heap;
+    type MemoryTypeInfo = MemoryTypeInfo;
+    type BlockParams = ();
 
-        let mut empty_block_index = None;
-        for (mem_block_i, mem_block) in self.memory_blocks.iter_mut().enumerate().rev() {
-            if let Some(mem_block) = mem_block {
-                let allocation = mem_block.sub_allocator.allocate(
-                    size,
-                    alignment,
-                    allocation_type,
-                    1,
-                    desc.name,
-                    #[cfg(feature = "std")]
-                    backtrace.clone(),
-                );
-
-                match allocation {
-                    Ok((offset, chunk_id)) => {
-                        return Ok(Allocation {
-                            chunk_id: Some(chunk_id),
-                            offset,
-                            size,
-                            memory_block_index: mem_block_i,
-                            memory_type_index: self.memory_type_index,
-                            heap: mem_block.heap.clone(),
-                            name: Some(desc.name.into()),
-                        });
-                    }
-                    Err(AllocationError::OutOfMemory) => {} // Block is full, continue search.
-                    Err(err) => return Err(err),            // Unhandled error, return.
-                }
-            } else if empty_block_index.is_none() {
-                empty_block_index = Some(mem_block_i);
-            }
-        }
-
-        let new_memory_block = MemoryBlock::new(
-            device,
-            memblock_size,
-            &self.heap_properties,
-            self.heap_category,
-            false,
-        )?;
-
-        let new_block_index = if let Some(block_index) = empty_block_index {
-            self.memory_blocks[block_index] = Some(new_memory_block);
-            block_index
-        } else {
-            self.memory_blocks.push(Some(new_memory_block));
-            self.memory_blocks.len() - 1
+    fn create_block(
+        &self,
+        memory_type: &MemoryTypeInfo,
+        _memory_type_index: usize,
+        size: u64,
+        _params: (),
+    ) -> Result<ID3D12Heap> {
+        let mut desc = D3D12_HEAP_DESC {
+            SizeInBytes: size,
+            Properties: memory_type.heap_properties,
+            Alignment: D3D12_DEFAULT_MSAA_RESOURCE_PLACEMENT_ALIGNMENT as u64,
+            ..Default::default()
+        };
+        desc.Flags = match memory_type.heap_category {
+            HeapCategory::All => D3D12_HEAP_FLAG_NONE,
+            HeapCategory::Buffer => D3D12_HEAP_FLAG_ALLOW_ONLY_BUFFERS,
+            HeapCategory::RtvDsvTexture => D3D12_HEAP_FLAG_ALLOW_ONLY_RT_DS_TEXTURES,
+            HeapCategory::OtherTexture => D3D12_HEAP_FLAG_ALLOW_ONLY_NON_RT_DS_TEXTURES,
         };
 
-        self.active_general_blocks += 1;
-
-        let mem_block = self.memory_blocks[new_block_index]
-            .as_mut()
-            .ok_or_else(|| AllocationError::Internal("Memory block must be Some".into()))?;
-        let allocation = mem_block.sub_allocator.allocate(
-            size,
-            alignment,
-            allocation_type,
-            1,
-            desc.name,
-            #[cfg(feature = "std")]
-            backtrace,
-        );
-        let (offset, chunk_id) = match allocation {
-            Err(AllocationError::OutOfMemory) => Err(AllocationError::Internal(
-                "Allocation that must succeed failed. This is a bug in the allocator.".into(),
-            )),
-            a => a,
-        }?;
-
-        Ok(Allocation {
-            chunk_id: Some(chunk_id),
-            offset,
-            size,
-            memory_block_index: new_block_index,
-            memory_type_index: self.memory_type_index,
-            heap: mem_block.heap.clone(),
-            name: Some(desc.name.into()),
-        })
-    }
-
-    #[allow(clippy::needless_pass_by_value)]
-    fn free(&mut self, allocation: Allocation) -> Result<()> {
-        let block_idx = allocation.memory_block_index;
-
-        let mem_block = self.memory_blocks[block_idx]
-            .as_mut()
-            .ok_or_else(|| AllocationError::Internal("Memory block must be Some.".into()))?;
-
-        mem_block.sub_allocator.free(allocation.chunk_id)?;
-
-        // We only want to destroy this now-empty block if it is either a dedicated/personal
-        // allocation, or a block supporting sub-allocations that is not the last one (ensuring
-        // there's always at least one block/allocator readily available).
-        let is_dedicated_or_not_last_general_block =
-            !mem_block.sub_allocator.supports_general_allocations()
-                || self.active_general_blocks > 1;
-        if mem_block.sub_allocator.is_empty() && is_dedicated_or_not_last_general_block {
-            let block = self.memory_blocks[block_idx]
-                .take()
-                .ok_or_else(|| AllocationError::Internal("Memory block must be Some.".into()))?;
-
-            if block.sub_allocator.supports_general_allocations() {
-                self.active_general_blocks -= 1;
-            }
-
-            // Note that `block` will be destroyed on `drop` here
+        let mut heap = None;
+        let hr = unsafe { self.CreateHeap(&desc, &mut heap) };
+        match hr {
+            Err(e) if e.code() == E_OUTOFMEMORY => Err(AllocationError::OutOfMemory),
+            Err(e) => Err(AllocationError::Internal(format!(
+                "ID3D12Device::CreateHeap failed: {e}"
+            ))),
+            Ok(()) => heap.ok_or_else(|| {
+                AllocationError::Internal("ID3D12Heap pointer is null, but should not be.".into())
+            }),
         }
+    }
 
-        Ok(())
+    fn destroy_block(&self, _memory_type: &MemoryTypeInfo, _heap: ID3D12Heap, _mapped: bool) {
+        // Note that `heap` will be released on `drop` here
     }
 }
 
@@ -507,6 +316,7 @@ pub struct Allocator {
     debug_settings: AllocatorDebugSettings,
     memory_types: Vec<MemoryType>,
     allocation_sizes: AllocationSizes,
+    fault_injector: FaultInjector,
 }
 
 impl Allocator {
@@ -593,20 +403,21 @@ impl Allocator {
         let memory_types = heap_types
             .iter()
             .enumerate()
-            .map(
-                |(i, &(heap_category, memory_location, heap_properties))| MemoryType {
-                    memory_blocks: Vec::default(),
-                    memory_location,
-                    heap_category,
-                    heap_properties,
-                    memory_type_index: i,
-                    active_general_blocks: 0,
-                    committed_allocations: CommittedAllocationStatistics {
-                        num_allocations: 0,
-                        total_size: 0,
+            .map(|(i, &(heap_category, memory_location, heap_properties))| {
+                MemoryType::new(
+                    i,
+                    heap_properties.Type != D3D12_HEAP_TYPE_DEFAULT,
+                    MemoryTypeInfo {
+                        memory_location,
+                        heap_category,
+                        heap_properties,
+                        committed_allocations: CommittedAllocationStatistics {
+                            num_allocations: 0,
+                            total_size: 0,
+                        },
                     },
-                },
-            )
+                )
+            })
             .collect::<Vec<_>>();
 
         Ok(Self {
@@ -614,6 +425,7 @@ impl Allocator {
             device,
             debug_settings: desc.debug_settings,
             allocation_sizes: desc.allocation_sizes,
+            fault_injector: FaultInjector::new(desc.debug_settings.fault_injection),
         })
     }
 
@@ -650,22 +462,42 @@ impl Allocator {
             .iter_mut()
             .find(|memory_type| {
                 let is_location_compatible = desc.location == MemoryLocation::Unknown
-                    || desc.location == memory_type.memory_location;
+                    || desc.location == memory_type.info.memory_location;
 
-                let is_category_compatible = memory_type.heap_category == HeapCategory::All
-                    || memory_type.heap_category == desc.resource_category.into();
+                let is_category_compatible = memory_type.info.heap_category == HeapCategory::All
+                    || memory_type.info.heap_category == desc.resource_category.into();
 
                 is_location_compatible && is_category_compatible
             })
             .ok_or(AllocationError::NoCompatibleMemoryTypeFound)?;
 
-        memory_type.allocate(
+        let sub_allocation = memory_type.allocate(
             &self.device,
-            desc,
-            #[cfg(feature = "std")]
-            backtrace,
+            &mut self.fault_injector,
+            &mut |_| Ok(()),
+            &SubAllocationDesc {
+                name: desc.name,
+                size,
+                alignment,
+                allocation_type: AllocationType::Linear,
+                granularity: 1,
+                dedicated: false,
+                block_params: (),
+                #[cfg(feature = "std")]
+                backtrace,
+            },
             &self.allocation_sizes,
-        )
+        )?;
+
+        Ok(Allocation {
+            chunk_id: Some(sub_allocation.chunk_id),
+            offset: sub_allocation.offset,
+            size,
+            memory_block_index: sub_allocation.memory_block_index,
+            memory_type_index: memory_type.memory_type_index,
+            heap: sub_allocation.memory,
+            name: Some(desc.name.into()),
+        })
     }
 
     pub fn free(&mut self, allocation: Allocation) -> Result<()> {
@@ -683,7 +515,12 @@ impl Allocator {
             return Ok(());
         }
 
-        self.memory_types[allocation.memory_type_index].free(allocation)?;
+        self.memory_types[allocation.memory_type_index].free(
+            &self.device,
+            &mut self.fault_injector,
+            allocation.memory_block_index,
+            allocation.chunk_id,
+        )?;
 
         Ok(())
     }
@@ -695,27 +532,16 @@ impl Allocator {
             return Ok(());
         }
 
-        let mem_type = &mut self.memory_types[allocation.memory_type_index];
-        let mem_block = mem_type.memory_blocks[allocation.memory_block_index]
-            .as_mut()
-            .ok_or_else(|| AllocationError::Internal("Memory block must be Some.".into()))?;
-
-        mem_block
-            .sub_allocator
-            .rename_allocation(allocation.chunk_id, name)?;
-
-        Ok(())
+        self.memory_types[allocation.memory_type_index].rename_allocation(
+            allocation.memory_block_index,
+            allocation.chunk_id,
+            name,
+        )
     }
 
     pub fn report_memory_leaks(&self, log_level: Level) {
-        for (mem_type_i, mem_type) in self.memory_types.iter().enumerate() {
-            for (block_i, mem_block) in mem_type.memory_blocks.iter().enumerate() {
-                if let Some(mem_block) = mem_block {
-                    mem_block
-                        .sub_allocator
-                        .report_memory_leaks(log_level, mem_type_i, block_i);
-                }
-            }
+        for mem_type in &self.memory_types {
+            mem_type.report_memory_leaks(log_level);
         }
     }
 
@@ -873,17 +699,18 @@ impl Allocator {
                     .find(|memory_type| {
                         let is_location_compatible = desc.memory_location
                             == MemoryLocation::Unknown
-                            || desc.memory_location == memory_type.memory_location;
+                            || desc.memory_location == memory_type.info.memory_location;
 
-                        let is_category_compatible = memory_type.heap_category == HeapCategory::All
-                            || memory_type.heap_category == desc.resource_category.into();
+                        let is_category_compatible = memory_type.info.heap_category
+                            == HeapCategory::All
+                            || memory_type.info.heap_category == desc.resource_category.into();
 
                         is_location_compatible && is_category_compatible
                     })
                     .ok_or(AllocationError::NoCompatibleMemoryTypeFound)?;
 
-                memory_type.committed_allocations.num_allocations += 1;
-                memory_type.committed_allocations.total_size += allocation_info.SizeInBytes;
+                memory_type.info.committed_allocations.num_allocations += 1;
+                memory_type.info.committed_allocations.total_size += allocation_info.SizeInBytes;
 
                 Ok(Resource {
                     name: desc.name.into(),
@@ -1009,51 +836,20 @@ impl Allocator {
             if let Some(memory_type_index) = resource.memory_type_index {
                 let memory_type = &mut self.memory_types[memory_type_index];
 
-                memory_type.committed_allocations.num_allocations -= 1;
-                memory_type.committed_allocations.total_size -= resource.size;
+                memory_type.info.committed_allocations.num_allocations -= 1;
+                memory_type.info.committed_allocations.total_size -= resource.size;
             }
             Ok(())
         }
     }
 
     pub fn generate_report(&self) -> AllocatorReport {
-        let mut allocations = vec![];
-        let mut blocks = vec![];
-        let mut total_capacity_bytes = 0;
-
-        for memory_type in &self.memory_types {
-            for block in memory_type.memory_blocks.iter().flatten() {
-                total_capacity_bytes += block.size;
-                let first_allocation = allocations.len();
-                allocations.extend(block.sub_allocator.report_allocations());
-                blocks.push(MemoryBlockReport {
-                    size: block.size,
-                    allocations: first_allocation..allocations.len(),
-                });
-            }
-        }
-
-        let total_allocated_bytes = allocations.iter().map(|report| report.size).sum();
-
-        AllocatorReport {
-            allocations,
-            blocks,
-            total_allocated_bytes,
-            total_capacity_bytes,
-        }
+        allocator::memory_type::generate_report(&self.memory_types)
     }
 
     /// Current total capacity of memory blocks allocated on the device, in bytes
     pub fn capacity(&self) -> u64 {
-        let mut total_capacity_bytes = 0;
-
-        for memory_type in &self.memory_types {
-            for block in memory_type.memory_blocks.iter().flatten() {
-                total_capacity_bytes += block.size;
-            }
-        }
-
-        total_capacity_bytes
+        self.memory_types.iter().map(MemoryType::capacity).sum()
     }
 }
 
@@ -1073,7 +869,7 @@ impl Drop for Allocator {
         // ID3D12Device before the ID3D12Heaps nested in these memory blocks), free
         // all remaining memory blocks manually first by dropping.
         for mem_type in self.memory_types.iter_mut() {
-            mem_type.memory_blocks.clear();
+            mem_type.destroy_blocks(&self.device);
         }
     }
 }
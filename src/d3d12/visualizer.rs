@@ -102,31 +102,35 @@ impl AllocatorVisualizer {
                                 .filter(|block| block.is_some())
                                 .count();
 
-                            ui.label(format!("heap category: {:?}", mem_type.heap_category));
+                            ui.label(format!("heap category: {:?}", mem_type.info.heap_category));
                             ui.label(format!(
                                 "Heap Type: {} ({})",
-                                format_heap_type(mem_type.heap_properties.Type),
-                                mem_type.heap_properties.Type.0
+                                format_heap_type(mem_type.info.heap_properties.Type),
+                                mem_type.info.heap_properties.Type.0
                             ));
                             ui.label(format!(
                                 "CpuPageProperty: {} ({})",
-                                format_cpu_page_property(mem_type.heap_properties.CPUPageProperty),
-                                mem_type.heap_properties.CPUPageProperty.0
+                                format_cpu_page_property(
+                                    mem_type.info.heap_properties.CPUPageProperty
+                                ),
+                                mem_type.info.heap_properties.CPUPageProperty.0
                             ));
                             ui.label(format!(
                                 "MemoryPoolPreference: {} ({})",
-                                format_memory_pool(mem_type.heap_properties.MemoryPoolPreference),
-                                mem_type.heap_properties.MemoryPoolPreference.0
+                                format_memory_pool(
+                                    mem_type.info.heap_properties.MemoryPoolPreference
+                                ),
+                                mem_type.info.heap_properties.MemoryPoolPreference.0
                             ));
                             ui.label(format!("total block size: {} KiB", total_block_size / 1024));
                             ui.label(format!("total allocated:  {} KiB", total_allocated / 1024));
                             ui.label(format!(
                                 "committed resource allocations: {}",
-                                mem_type.committed_allocations.num_allocations
+                                mem_type.info.committed_allocations.num_allocations
                             ));
                             ui.label(format!(
                                 "total committed resource allocations: {} KiB",
-                                mem_type.committed_allocations.total_size
+                                mem_type.info.committed_allocations.total_size
                             ));
                             ui.label(format!("block count: {active_block_count}"));
 
@@ -139,7 +143,7 @@ impl AllocatorVisualizer {
                                         "allocated: {} KiB",
                                         block.sub_allocator.allocated() / 1024
                                     ));
-                                    ui.label(format!("D3D12 heap: {:?}", block.heap));
+                                    ui.label(format!("D3D12 heap: {:?}", block.memory));
                                     block.sub_allocator.draw_base_info(ui);
 
                                     if block.sub_allocator.supports_visualization()
@@ -12,12 +12,8 @@
 //! # let physical_device = todo!();
 //!
 //! let mut allocator = Allocator::new(&AllocatorCreateDesc {
-//!     instance,
-//!     device,
-//!     physical_device,
-//!     debug_settings: Default::default(),
-//!     buffer_device_address: true,  // Ideally, check the BufferDeviceAddressFeatures struct.
-//!     allocation_sizes: Default::default(),
+//!     buffer_device_address: true,  // Must match the enabled BufferDeviceAddressFeatures.
+//!     ..AllocatorCreateDesc::new(instance, device, physical_device)
 //! });
 //! # }
 //! # #[cfg(not(feature = "vulkan"))]
@@ -36,12 +32,8 @@
 //! # let instance = todo!();
 //! # let physical_device = todo!();
 //! # let mut allocator = Allocator::new(&AllocatorCreateDesc {
-//! #     instance,
-//! #     device,
-//! #     physical_device,
-//! #     debug_settings: Default::default(),
-//! #     buffer_device_address: true,  // Ideally, check the BufferDeviceAddressFeatures struct.
-//! #     allocation_sizes: Default::default(),
+//! #     buffer_device_address: true,  // Must match the enabled BufferDeviceAddressFeatures.
+//! #     ..AllocatorCreateDesc::new(instance, device, physical_device)
 //! # }).unwrap();
 //!
 //! // Setup vulkan info
@@ -59,6 +51,7 @@
 //!         location: MemoryLocation::CpuToGpu,
 //!         linear: true, // Buffers are always linear
 //!         allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+//!         ..Default::default()
 //!     }).unwrap();
 //!
 //! // Bind memory to the buffer
@@ -229,7 +222,7 @@ pub use result::*;
 
 pub(crate) mod allocator;
 
-pub use allocator::{AllocationReport, AllocatorReport, MemoryBlockReport};
+pub use allocator::{AllocationReport, AllocatorReport, BudgetGroupReport, MemoryBlockReport};
 
 #[cfg(feature = "visualizer")]
 pub mod visualizer;
@@ -275,6 +268,9 @@ pub struct AllocatorDebugSettings {
     /// Log out stack traces when either `log_allocations` or `log_frees` is enabled.
     #[cfg(feature = "std")]
     pub log_stack_traces: bool,
+    /// Deliberately fails the creation of new memory blocks, to exercise out-of-memory handling
+    /// and fallback paths deterministically.  Never enable this in shipping applications.
+    pub fault_injection: Option<FaultInjection>,
 }
 
 impl Default for AllocatorDebugSettings {
@@ -288,10 +284,60 @@ impl Default for AllocatorDebugSettings {
             log_frees: false,
             #[cfg(feature = "std")]
             log_stack_traces: false,
+            fault_injection: None,
         }
     }
 }
 
+/// Decides which memory block allocations fail when [`FaultInjection`] is enabled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultInjectionTrigger {
+    /// Fails every memory block allocation after the first `n` have succeeded.
+    AfterBlockCount(u64),
+    /// Fails memory block allocations that would grow the total size of all live memory blocks
+    /// past this many bytes.
+    ByteLimit(u64),
+    /// Fails memory block allocations at random with the given `probability` between `0.0` and
+    /// `1.0`.  The sequence of failures is fully determined by `seed`.
+    Random { seed: u64, probability: f32 },
+}
+
+/// The error returned for a memory block allocation that is failed by [`FaultInjection`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultInjectionError {
+    /// Fail as if the device ran out of memory, returning [`AllocationError::OutOfMemory`].
+    OutOfMemory,
+    /// Fail as if mapping the new memory block failed, returning [`AllocationError::FailedToMap`].
+    /// The memory block is created and destroyed again, also for memory that is not host visible.
+    FailedToMap,
+}
+
+/// Configures deliberate memory block allocation failures through
+/// [`AllocatorDebugSettings::fault_injection`].
+///
+/// Only the creation of new memory blocks is affected; allocations that fit in an existing
+/// memory block always succeed.
+///
+/// # Example
+///
+/// ```
+/// use gpu_allocator::{
+///     AllocatorDebugSettings, FaultInjection, FaultInjectionError, FaultInjectionTrigger,
+/// };
+///
+/// let mut debug_settings = AllocatorDebugSettings::default();
+/// // Pretend the device only has 512MB of memory.
+/// debug_settings.fault_injection = Some(FaultInjection {
+///     trigger: FaultInjectionTrigger::ByteLimit(512 * 1024 * 1024),
+///     error: FaultInjectionError::OutOfMemory,
+/// });
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FaultInjection {
+    pub trigger: FaultInjectionTrigger,
+    pub error: FaultInjectionError,
+}
+
 /// The sizes of the memory blocks that the allocator will create.
 ///
 /// Useful for tuning the allocator to your application's needs. For example most games will be fine with the default
@@ -415,6 +461,18 @@ impl AllocationSizes {
         new_size
     }
 
+    /// Returns these sizes multiplied by `scale`, and clamped to at most `max_size`.
+    #[cfg(feature = "vulkan")]
+    pub(crate) fn scaled(&self, scale: u64, max_size: u64) -> Self {
+        let scale = |size: u64| size.saturating_mul(scale).min(max_size);
+        Self {
+            min_device_memblock_size: scale(self.min_device_memblock_size),
+            max_device_memblock_size: scale(self.max_device_memblock_size),
+            min_host_memblock_size: scale(self.min_host_memblock_size),
+            max_host_memblock_size: scale(self.max_host_memblock_size),
+        }
+    }
+
     /// Used internally to decide the size of a shared memory block
     /// based within the allowed range, based on the number of
     /// existing allocations. The more blocks there already are
@@ -447,3 +505,37 @@ impl Default for AllocationSizes {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::AllocationSizes;
+
+    const MB: u64 = 1024 * 1024;
+
+    #[test]
+    #[cfg(feature = "vulkan")]
+    fn scaled_sizes_are_clamped() {
+        let sizes = AllocationSizes::new(8 * MB, 4 * MB).with_max_device_memblock_size(64 * MB);
+
+        let scaled = sizes.scaled(4, u64::MAX);
+        assert_eq!(scaled.get_memblock_size(false, 0), 32 * MB);
+        assert_eq!(scaled.get_memblock_size(false, 7), 256 * MB);
+        assert_eq!(scaled.get_memblock_size(true, 7), 16 * MB);
+
+        let clamped = sizes.scaled(4, 16 * MB);
+        assert_eq!(clamped.get_memblock_size(false, 0), 16 * MB);
+        assert_eq!(clamped.get_memblock_size(false, 7), 16 * MB);
+        assert_eq!(clamped.get_memblock_size(true, 0), 16 * MB);
+
+        let saturated = sizes.scaled(u64::MAX, u64::MAX);
+        assert_eq!(saturated.get_memblock_size(false, 0), u64::MAX);
+    }
+
+    #[test]
+    #[cfg(feature = "vulkan")]
+    fn unscaled_sizes_are_unchanged() {
+        let sizes = AllocationSizes::default().scaled(1, u64::MAX);
+        assert_eq!(sizes.get_memblock_size(false, 0), 256 * MB);
+        assert_eq!(sizes.get_memblock_size(true, 0), 64 * MB);
+    }
+}
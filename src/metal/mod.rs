@@ -21,8 +21,7 @@ pub use visualizer::AllocatorVisualizer;
 
 use crate::{
     allocator::{
-        AllocationType, AllocatorReport, DedicatedBlockAllocator, FreeListAllocator,
-        MemoryBlockReport, SubAllocator,
+        self, AllocationType, AllocatorReport, FaultInjector, MemoryDevice, SubAllocationDesc,
     },
     AllocationError, AllocationSizes, AllocatorDebugSettings, MemoryLocation, Result,
 };
@@ -160,6 +159,7 @@ pub struct Allocator {
     debug_settings: AllocatorDebugSettings,
     memory_types: Vec<MemoryType>,
     allocation_sizes: AllocationSizes,
+    fault_injector: FaultInjector,
 }
 
 impl core::fmt::Debug for Allocator {
@@ -184,240 +184,53 @@ pub struct CommittedAllocationStatistics {
     pub total_size: u64,
 }
 
+/// Metal-specific description of a memory type.
 #[derive(Debug)]
-struct MemoryBlock {
-    heap: Retained<ProtocolObject<dyn MTLHeap>>,
-    size: u64,
-    sub_allocator: Box<dyn SubAllocator>,
-}
-
-impl MemoryBlock {
-    fn new(
-        device: &ProtocolObject<dyn MTLDevice>,
-        size: u64,
-        heap_descriptor: &MTLHeapDescriptor,
-        dedicated: bool,
-        memory_location: MemoryLocation,
-    ) -> Result<Self> {
-        heap_descriptor.setSize(size as usize);
-
-        let heap = device
-            .newHeapWithDescriptor(heap_descriptor)
-            .ok_or_else(|| AllocationError::Internal("No MTLHeap was returned".to_string()))?;
-
-        heap.setLabel(Some(&NSString::from_str(&format!(
-            "MemoryBlock {memory_location:?}"
-        ))));
-
-        let sub_allocator: Box<dyn SubAllocator> = if dedicated {
-            Box::new(DedicatedBlockAllocator::new(size))
-        } else {
-            Box::new(FreeListAllocator::new(size))
-        };
-
-        Ok(Self {
-            heap,
-            size,
-            sub_allocator,
-        })
-    }
-}
-
-#[derive(Debug)]
-struct MemoryType {
+pub(crate) struct MemoryTypeInfo {
     global_residency_set: Option<Retained<ProtocolObject<dyn MTLResidencySet>>>,
-    memory_blocks: Vec<Option<MemoryBlock>>,
     _committed_allocations: CommittedAllocationStatistics,
     memory_location: MemoryLocation,
     heap_properties: Retained<MTLHeapDescriptor>,
-    memory_type_index: usize,
-    active_general_blocks: usize,
 }
 
-impl MemoryType {
-    fn allocate(
-        &mut self,
-        device: &ProtocolObject<dyn MTLDevice>,
-        desc: &AllocationCreateDesc<'_>,
-        #[cfg(feature = "std")] backtrace: Arc<Backtrace>,
-        allocation_sizes: &AllocationSizes,
-    ) -> Result<Allocation> {
-        let allocation_type = AllocationType::Linear;
+type MemoryType = allocator::MemoryType<Retained<ProtocolObject<dyn MTLDevice>>>;
 
-        let is_host = self.heap_properties.storageMode() != MTLStorageMode::Private;
-        let memblock_size = allocation_sizes.get_memblock_size(is_host, self.active_general_blocks);
+impl MemoryDevice for Retained<ProtocolObject<dyn MTLDevice>> {
+    type Memory = Retained<ProtocolObject<dyn MTLHeap>>;
+    type MemoryTypeInfo = MemoryTypeInfo;
+    type BlockParams = ();
 
-        let size = desc.size;
-        let alignment = desc.alignment;
-
-        // Create a dedicated block for large memory allocations
-        if size > memblock_size {
-            let mem_block = MemoryBlock::new(
-                device,
-                size,
-                &self.heap_properties,
-                true,
-                self.memory_location,
-            )?;
-
-            if let Some(rs) = &self.global_residency_set {
-                unsafe { rs.addAllocation(mem_block.heap.as_ref()) }
-            }
-
-            let block_index = self.memory_blocks.iter().position(|block| block.is_none());
-            let block_index = match block_index {
-                Some(i) => {
-                    self.memory_blocks[i].replace(mem_block);
-                    i
-                }
-                None => {
-                    self.memory_blocks.push(Some(mem_block));
-                    self.memory_blocks.len() - 1
-                }
-            };
-
-            let mem_block = self.memory_blocks[block_index]
-                .as_mut()
-                .ok_or_else(|| AllocationError::Internal("Memory block must be Some".into()))?;
-
-            let (offset, chunk_id) = mem_block.sub_allocator.allocate(
-                size,
-                alignment,
-                allocation_type,
-                1,
-                desc.name,
-                #[cfg(feature = "std")]
-                backtrace,
-            )?;
-
-            return Ok(Allocation {
-                chunk_id: Some(chunk_id),
-                size,
-                offset,
-                memory_block_index: block_index,
-                memory_type_index: self.memory_type_index,
-                heap: mem_block.heap.clone(),
-                name: Some(desc.name.into()),
-            });
-        }
+    fn create_block(
+        &self,
+        memory_type: &MemoryTypeInfo,
+        _memory_type_index: usize,
+        size: u64,
+        _params: (),
+    ) -> Result<Self::Memory> {
+        memory_type.heap_properties.setSize(size as usize);
 
-        let mut empty_block_index = None;
-        for (mem_block_i, mem_block) in self.memory_blocks.iter_mut().enumerate().rev() {
-            if let Some(mem_block) = mem_block {
-                let allocation = mem_block.sub_allocator.allocate(
-                    size,
-                    alignment,
-                    allocation_type,
-                    1,
-                    desc.name,
-                    #[cfg(feature = "std")]
-                    backtrace.clone(),
-                );
-
-                match allocation {
-                    Ok((offset, chunk_id)) => {
-                        return Ok(Allocation {
-                            chunk_id: Some(chunk_id),
-                            offset,
-                            size,
-                            memory_block_index: mem_block_i,
-                            memory_type_index: self.memory_type_index,
-                            heap: mem_block.heap.clone(),
-                            name: Some(desc.name.into()),
-                        });
-                    }
-                    Err(AllocationError::OutOfMemory) => {} // Block is full, continue search.
-                    Err(err) => return Err(err),            // Unhandled error, return.
-                }
-            } else if empty_block_index.is_none() {
-                empty_block_index = Some(mem_block_i);
-            }
-        }
+        let heap = self
+            .newHeapWithDescriptor(&memory_type.heap_properties)
+            .ok_or_else(|| AllocationError::Internal("No MTLHeap was returned".to_string()))?;
 
-        let mem_block = MemoryBlock::new(
-            device,
-            memblock_size,
-            &self.heap_properties,
-            false,
-            self.memory_location,
-        )?;
+        heap.setLabel(Some(&NSString::from_str(&format!(
+            "MemoryBlock {:?}",
+            memory_type.memory_location
+        ))));
 
-        if let Some(rs) = &self.global_residency_set {
-            unsafe { rs.addAllocation(mem_block.heap.as_ref()) }
+        if let Some(rs) = &memory_type.global_residency_set {
+            unsafe { rs.addAllocation(heap.as_ref()) }
         }
 
-        let new_block_index = if let Some(block_index) = empty_block_index {
-            self.memory_blocks[block_index] = Some(mem_block);
-            block_index
-        } else {
-            self.memory_blocks.push(Some(mem_block));
-            self.memory_blocks.len() - 1
-        };
-
-        self.active_general_blocks += 1;
-
-        let mem_block = self.memory_blocks[new_block_index]
-            .as_mut()
-            .ok_or_else(|| AllocationError::Internal("Memory block must be Some".into()))?;
-        let allocation = mem_block.sub_allocator.allocate(
-            size,
-            alignment,
-            allocation_type,
-            1,
-            desc.name,
-            #[cfg(feature = "std")]
-            backtrace,
-        );
-        let (offset, chunk_id) = match allocation {
-            Err(AllocationError::OutOfMemory) => Err(AllocationError::Internal(
-                "Allocation that must succeed failed. This is a bug in the allocator.".into(),
-            )),
-            a => a,
-        }?;
-
-        Ok(Allocation {
-            chunk_id: Some(chunk_id),
-            offset,
-            size,
-            memory_block_index: new_block_index,
-            memory_type_index: self.memory_type_index,
-            heap: mem_block.heap.clone(),
-            name: Some(desc.name.into()),
-        })
+        Ok(heap)
     }
 
-    fn free(&mut self, allocation: &Allocation) -> Result<()> {
-        let block_idx = allocation.memory_block_index;
-
-        let mem_block = self.memory_blocks[block_idx]
-            .as_mut()
-            .ok_or_else(|| AllocationError::Internal("Memory block must be Some.".into()))?;
-
-        mem_block.sub_allocator.free(allocation.chunk_id)?;
-
-        // We only want to destroy this now-empty block if it is either a dedicated/personal
-        // allocation, or a block supporting sub-allocations that is not the last one (ensuring
-        // there's always at least one block/allocator readily available).
-        let is_dedicated_or_not_last_general_block =
-            !mem_block.sub_allocator.supports_general_allocations()
-                || self.active_general_blocks > 1;
-        if mem_block.sub_allocator.is_empty() && is_dedicated_or_not_last_general_block {
-            let block = self.memory_blocks[block_idx]
-                .take()
-                .ok_or_else(|| AllocationError::Internal("Memory block must be Some.".into()))?;
-
-            if block.sub_allocator.supports_general_allocations() {
-                self.active_general_blocks -= 1;
-            }
-
-            if let Some(rs) = &self.global_residency_set {
-                unsafe { rs.removeAllocation(block.heap.as_ref()) }
-            }
-
-            // Note that `block` will be destroyed on `drop` here
+    fn destroy_block(&self, memory_type: &MemoryTypeInfo, heap: Self::Memory, _mapped: bool) {
+        if let Some(rs) = &memory_type.global_residency_set {
+            unsafe { rs.removeAllocation(heap.as_ref()) }
         }
 
-        Ok(())
+        // Note that `heap` will be destroyed on `drop` here
     }
 }
 
@@ -462,17 +275,20 @@ impl Allocator {
         let memory_types = heap_types
             .into_iter()
             .enumerate()
-            .map(|(i, (memory_location, heap_descriptor))| MemoryType {
-                global_residency_set: global_residency_set.clone(),
-                memory_blocks: vec![],
-                _committed_allocations: CommittedAllocationStatistics {
-                    num_allocations: 0,
-                    total_size: 0,
-                },
-                memory_location,
-                heap_properties: heap_descriptor,
-                memory_type_index: i,
-                active_general_blocks: 0,
+            .map(|(i, (memory_location, heap_descriptor))| {
+                MemoryType::new(
+                    i,
+                    heap_descriptor.storageMode() != MTLStorageMode::Private,
+                    MemoryTypeInfo {
+                        global_residency_set: global_residency_set.clone(),
+                        _committed_allocations: CommittedAllocationStatistics {
+                            num_allocations: 0,
+                            total_size: 0,
+                        },
+                        memory_location,
+                        heap_properties: heap_descriptor,
+                    },
+                )
             })
             .collect();
 
@@ -481,6 +297,7 @@ impl Allocator {
             debug_settings: desc.debug_settings,
             memory_types,
             allocation_sizes: desc.allocation_sizes,
+            fault_injector: FaultInjector::new(desc.debug_settings.fault_injection),
             global_residency_set,
         })
     }
@@ -519,17 +336,37 @@ impl Allocator {
             .find(|memory_type| {
                 // Is location compatible
                 desc.location == MemoryLocation::Unknown
-                    || desc.location == memory_type.memory_location
+                    || desc.location == memory_type.info.memory_location
             })
             .ok_or(AllocationError::NoCompatibleMemoryTypeFound)?;
 
-        memory_type.allocate(
+        let sub_allocation = memory_type.allocate(
             &self.device,
-            desc,
-            #[cfg(feature = "std")]
-            backtrace,
+            &mut self.fault_injector,
+            &mut |_| Ok(()),
+            &SubAllocationDesc {
+                name: desc.name,
+                size,
+                alignment,
+                allocation_type: AllocationType::Linear,
+                granularity: 1,
+                dedicated: false,
+                block_params: (),
+                #[cfg(feature = "std")]
+                backtrace,
+            },
             &self.allocation_sizes,
-        )
+        )?;
+
+        Ok(Allocation {
+            chunk_id: Some(sub_allocation.chunk_id),
+            offset: sub_allocation.offset,
+            size,
+            memory_block_index: sub_allocation.memory_block_index,
+            memory_type_index: memory_type.memory_type_index,
+            heap: sub_allocation.memory,
+            name: Some(desc.name.into()),
+        })
     }
 
     pub fn free(&mut self, allocation: &Allocation) -> Result<()> {
@@ -546,7 +383,12 @@ impl Allocator {
         if allocation.is_null() {
             return Ok(());
         }
-        self.memory_types[allocation.memory_type_index].free(allocation)?;
+        self.memory_types[allocation.memory_type_index].free(
+            &self.device,
+            &mut self.fault_injector,
+            allocation.memory_block_index,
+            allocation.chunk_id,
+        )?;
         Ok(())
     }
 
@@ -557,48 +399,17 @@ impl Allocator {
                 .memory_blocks
                 .iter()
                 .flatten()
-                .map(|block| block.heap.as_ref())
+                .map(|block| block.memory.as_ref())
         })
     }
 
     pub fn generate_report(&self) -> AllocatorReport {
-        let mut allocations = vec![];
-        let mut blocks = vec![];
-        let mut total_capacity_bytes = 0;
-
-        for memory_type in &self.memory_types {
-            for block in memory_type.memory_blocks.iter().flatten() {
-                total_capacity_bytes += block.size;
-                let first_allocation = allocations.len();
-                allocations.extend(block.sub_allocator.report_allocations());
-                blocks.push(MemoryBlockReport {
-                    size: block.size,
-                    allocations: first_allocation..allocations.len(),
-                });
-            }
-        }
-
-        let total_allocated_bytes = allocations.iter().map(|report| report.size).sum();
-
-        AllocatorReport {
-            allocations,
-            blocks,
-            total_allocated_bytes,
-            total_capacity_bytes,
-        }
+        allocator::memory_type::generate_report(&self.memory_types)
     }
 
     /// Current total capacity of memory blocks allocated on the device, in bytes
     pub fn capacity(&self) -> u64 {
-        let mut total_capacity_bytes = 0;
-
-        for memory_type in &self.memory_types {
-            for block in memory_type.memory_blocks.iter().flatten() {
-                total_capacity_bytes += block.size;
-            }
-        }
-
-        total_capacity_bytes
+        self.memory_types.iter().map(MemoryType::capacity).sum()
     }
 
     /// Optional residency set containing all heap allocations created/owned by this allocator to
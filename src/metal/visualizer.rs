@@ -66,7 +66,7 @@ impl AllocatorVisualizer {
                                 .filter(|block| block.is_some())
                                 .count();
 
-                            ui.label(format!("properties: {:?}", mem_type.heap_properties));
+                            ui.label(format!("properties: {:?}", mem_type.info.heap_properties));
                             ui.label(format!("memory type index: {}", mem_type.memory_type_index));
                             ui.label(format!("total block size: {} KiB", total_block_size / 1024));
                             ui.label(format!("total allocated:  {} KiB", total_allocated / 1024));
@@ -81,7 +81,7 @@ impl AllocatorVisualizer {
                                         "allocated: {} KiB",
                                         block.sub_allocator.allocated() / 1024
                                     ));
-                                    ui.label(format!("Heap: {:?}", &block.heap));
+                                    ui.label(format!("Heap: {:?}", &block.memory));
 
                                     block.sub_allocator.draw_base_info(ui);
 
@@ -1,4 +1,5 @@
 use alloc::string::String;
+use core::ops::Range;
 
 use thiserror::Error;
 
@@ -12,6 +13,24 @@ pub enum AllocationError {
     NoCompatibleMemoryTypeFound,
     #[error("Invalid AllocationCreateDesc")]
     InvalidAllocationCreateDesc,
+    #[error("Budget group `{0}` exceeded")]
+    BudgetGroupExceeded(String),
+    #[error("Budget group does not belong to this allocator")]
+    UnknownBudgetGroup,
+    #[error("Range {0:?} is out of bounds of the allocation")]
+    InvalidRange(Range<u64>),
+    #[error("Offset {0} is not aligned to {1} bytes")]
+    MisalignedOffset(u64, u64),
+    #[error("Allocation of {0} bytes exceeds the maximum allocation size of {1} bytes")]
+    AllocationSizeExceeded(u64, u64),
+    #[error("Device limit of {0} memory allocations reached")]
+    AllocationCountExceeded(u32),
+    #[error("Failed to export memory: {0}")]
+    ExportFailed(String),
+    #[error("Memory block was not adopted")]
+    MemoryBlockNotAdopted,
+    #[error("Memory block still has live allocations or mappings")]
+    MemoryBlockInUse,
     #[error("Invalid AllocatorCreateDesc {0}")]
     InvalidAllocatorCreateDesc(String),
     #[error("Internal error: {0}")]
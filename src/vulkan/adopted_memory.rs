@@ -0,0 +1,157 @@
+use ash::vk;
+use log::debug;
+
+use super::{Allocation, AllocationScheme, Allocator, BlockParams, ImportMemoryDesc};
+use crate::{AllocationError, MemoryLocation, Result};
+
+/// Who frees a `VkDeviceMemory` adopted with [`Allocator::adopt_memory()`] or
+/// [`Allocator::adopt_memory_block()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryOwnership {
+    /// The allocator frees the memory once it is released, or when the allocator is dropped.
+    Allocator,
+    /// The memory is handed back unmapped on release, and the caller stays responsible for
+    /// freeing it.
+    Caller,
+}
+
+/// Describes a `VkDeviceMemory` to adopt with [`Allocator::adopt_memory()`].
+#[derive(Clone, Debug)]
+pub struct AdoptMemoryDesc<'a> {
+    /// Name of the allocation, for tracking and debugging purposes
+    pub name: &'a str,
+    /// Index of the memory type the memory was allocated from
+    pub memory_type_index: u32,
+    /// Size the memory was allocated with, in bytes
+    pub size: u64,
+    /// If the resource is linear (buffer / linear texture) or a regular (tiled) texture.
+    pub linear: bool,
+    pub ownership: MemoryOwnership,
+}
+
+impl Allocator {
+    fn check_adopted_memory(&self, memory_type_index: u32, size: u64) -> Result<usize> {
+        let memory_type_index = memory_type_index as usize;
+        if memory_type_index >= self.memory_types.len() || size == 0 {
+            return Err(AllocationError::InvalidAllocationCreateDesc);
+        }
+        Ok(memory_type_index)
+    }
+
+    /// Registers `memory` that was allocated outside of this allocator, such as by a video
+    /// decoder, as a dedicated allocation that shows up in reports and leak checks.
+    ///
+    /// Once the allocation is passed to [`Allocator::free()`], `memory` is freed with
+    /// [`MemoryOwnership::Allocator`], or unmapped and left alone with
+    /// [`MemoryOwnership::Caller`].
+    ///
+    /// # Safety
+    ///
+    /// `memory` must be a valid `VkDeviceMemory` of `desc.size` bytes from memory type
+    /// `desc.memory_type_index`, allocated on the device of this allocator and not mapped.  It must
+    /// not be freed elsewhere while the allocation is alive, nor at all with
+    /// [`MemoryOwnership::Allocator`].
+    pub unsafe fn adopt_memory(
+        &mut self,
+        memory: vk::DeviceMemory,
+        desc: &AdoptMemoryDesc<'_>,
+    ) -> Result<Allocation> {
+        let memory_type_index = self.check_adopted_memory(desc.memory_type_index, desc.size)?;
+
+        if self.debug_settings.log_allocations {
+            debug!("Adopting `{}` of {} bytes.", &desc.name, desc.size);
+        }
+
+        let params = BlockParams {
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            export_handle_types: vk::ExternalMemoryHandleTypeFlags::empty(),
+            priority: None,
+            device_mask: 0,
+            buffer_device_address: false,
+        };
+
+        self.allocate_foreign_memory(
+            memory_type_index,
+            memory,
+            &ImportMemoryDesc {
+                name: desc.name,
+                requirements: vk::MemoryRequirements {
+                    size: desc.size,
+                    alignment: 1,
+                    memory_type_bits: 1 << memory_type_index,
+                },
+                location: MemoryLocation::Unknown,
+                linear: desc.linear,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            },
+            params,
+            desc.ownership == MemoryOwnership::Allocator,
+        )
+    }
+
+    /// Registers `memory` of `size` bytes from memory type `memory_type_index`, which was allocated
+    /// outside of this allocator, as a general memory block.  Later [`Allocator::allocate()`] calls
+    /// for that memory type sub-allocate from it like from any other block.
+    ///
+    /// The block stays, even when empty, until it is released with
+    /// [`Allocator::release_memory_block()`] or the allocator is dropped.
+    ///
+    /// # Safety
+    ///
+    /// `memory` must be a valid `VkDeviceMemory` of `size` bytes from memory type
+    /// `memory_type_index`, allocated on the device of this allocator without dedicated or export
+    /// info, and not mapped.  It must not be freed elsewhere while the block is registered, nor at
+    /// all with [`MemoryOwnership::Allocator`].
+    pub unsafe fn adopt_memory_block(
+        &mut self,
+        memory: vk::DeviceMemory,
+        memory_type_index: u32,
+        size: u64,
+        ownership: MemoryOwnership,
+    ) -> Result<()> {
+        let memory_type_index = self.check_adopted_memory(memory_type_index, size)?;
+
+        if self.debug_settings.log_allocations {
+            debug!("Adopting memory block of {size} bytes in memory type {memory_type_index}.");
+        }
+
+        let params = BlockParams {
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            export_handle_types: vk::ExternalMemoryHandleTypeFlags::empty(),
+            priority: None,
+            device_mask: 0,
+            buffer_device_address: false,
+        };
+
+        self.memory_types[memory_type_index].adopt_block(
+            &self.device,
+            memory,
+            size,
+            params,
+            ownership == MemoryOwnership::Allocator,
+        )?;
+
+        Ok(())
+    }
+
+    /// Unregisters a memory block added with [`Allocator::adopt_memory_block()`], which must not
+    /// have any allocations left.  Frees `memory` with [`MemoryOwnership::Allocator`], or unmaps it
+    /// and hands it back to the caller with [`MemoryOwnership::Caller`].
+    ///
+    /// Fails with [`AllocationError::MemoryBlockNotAdopted`] if `memory` is not an adopted block,
+    /// and with [`AllocationError::MemoryBlockInUse`] while it still has allocations or mappings.
+    pub fn release_memory_block(&mut self, memory: vk::DeviceMemory) -> Result<()> {
+        for memory_type in &mut self.memory_types {
+            let memory_block_index = memory_type
+                .memory_blocks
+                .iter()
+                .position(|block| block.as_ref().is_some_and(|block| block.memory == memory));
+
+            if let Some(memory_block_index) = memory_block_index {
+                return memory_type.release_block(&self.device, memory_block_index);
+            }
+        }
+
+        Err(AllocationError::MemoryBlockNotAdopted)
+    }
+}
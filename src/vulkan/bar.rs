@@ -0,0 +1,240 @@
+use ash::vk;
+
+use super::Allocator;
+
+/// The memory heap behind the `DEVICE_LOCAL | HOST_VISIBLE` memory types, through which the host
+/// can write to device-local memory directly, as returned by [`Allocator::bar_heap()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BarHeap {
+    /// Index of the memory heap.
+    pub heap_index: usize,
+    /// Size of the memory heap reported by the driver, in bytes.
+    pub size: u64,
+    /// Whether all device-local memory is mappable, as with Resizable BAR or on integrated GPUs.
+    /// If not, the heap is typically only 256 MiB and best kept for data that benefits most.
+    pub resizable: bool,
+}
+
+/// Finds the [`BarHeap`] of a device, if any.
+pub(super) fn find_bar_heap(
+    memory_types: &[vk::MemoryType],
+    memory_heaps: &[vk::MemoryHeap],
+) -> Option<BarHeap> {
+    let heap_index = memory_types
+        .iter()
+        .find(|memory_type| {
+            memory_type.property_flags.contains(
+                vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::HOST_VISIBLE,
+            )
+        })?
+        .heap_index as usize;
+
+    let size = memory_heaps[heap_index].size;
+    let largest_device_local_heap = memory_heaps
+        .iter()
+        .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|heap| heap.size)
+        .max()
+        .unwrap_or(0);
+
+    Some(BarHeap {
+        heap_index,
+        size,
+        resizable: size >= largest_device_local_heap,
+    })
+}
+
+/// Returns [`true`] for integrated GPUs and devices where every device-local memory type is also
+/// host-visible, as long as there is device-local memory the host can map at all.
+pub(super) fn is_unified_memory(
+    device_type: vk::PhysicalDeviceType,
+    memory_types: &[vk::MemoryType],
+) -> bool {
+    let device_local = vk::MemoryPropertyFlags::DEVICE_LOCAL;
+    let host_visible = vk::MemoryPropertyFlags::HOST_VISIBLE;
+
+    let mut device_local_types = memory_types
+        .iter()
+        .filter(|memory_type| memory_type.property_flags.contains(device_local));
+
+    let any_mappable = device_local_types
+        .clone()
+        .any(|memory_type| memory_type.property_flags.contains(host_visible));
+    let all_mappable =
+        device_local_types.all(|memory_type| memory_type.property_flags.contains(host_visible));
+
+    any_mappable && (all_mappable || device_type == vk::PhysicalDeviceType::INTEGRATED_GPU)
+}
+
+impl Allocator {
+    /// Returns the heap through which the host can write to device-local memory directly, or
+    /// [`None`] if the device has no such memory.
+    pub fn bar_heap(&self) -> Option<BarHeap> {
+        self.bar_heap
+    }
+
+    /// Returns [`true`] for integrated and other unified memory devices, where the host can access
+    /// device-local memory directly.
+    ///
+    /// In this mode, all [`crate::MemoryLocation::GpuOnly`] allocations of resources that support
+    /// host-visible memory carry a [`super::Allocation::mapped_ptr()`], so uploads can skip staging
+    /// buffers.
+    pub fn is_unified_memory(&self) -> bool {
+        self.unified_memory
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    fn memory_type(property_flags: vk::MemoryPropertyFlags, heap_index: u32) -> vk::MemoryType {
+        vk::MemoryType {
+            property_flags,
+            heap_index,
+        }
+    }
+
+    fn device_local_heap(size: u64) -> vk::MemoryHeap {
+        vk::MemoryHeap {
+            size,
+            flags: vk::MemoryHeapFlags::DEVICE_LOCAL,
+        }
+    }
+
+    fn host_heap(size: u64) -> vk::MemoryHeap {
+        vk::MemoryHeap {
+            size,
+            flags: vk::MemoryHeapFlags::empty(),
+        }
+    }
+
+    const DEVICE_LOCAL: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::DEVICE_LOCAL;
+    const HOST_VISIBLE: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::from_raw(
+        vk::MemoryPropertyFlags::HOST_VISIBLE.as_raw()
+            | vk::MemoryPropertyFlags::HOST_COHERENT.as_raw(),
+    );
+    const BAR: vk::MemoryPropertyFlags =
+        vk::MemoryPropertyFlags::from_raw(DEVICE_LOCAL.as_raw() | HOST_VISIBLE.as_raw());
+
+    /// A discrete GPU with a 256 MiB BAR in front of 8 GiB of VRAM.
+    fn discrete() -> ([vk::MemoryType; 3], [vk::MemoryHeap; 3]) {
+        (
+            [
+                memory_type(DEVICE_LOCAL, 0),
+                memory_type(HOST_VISIBLE, 1),
+                memory_type(BAR, 2),
+            ],
+            [
+                device_local_heap(8192 * MB),
+                host_heap(16384 * MB),
+                device_local_heap(256 * MB),
+            ],
+        )
+    }
+
+    /// A discrete GPU with Resizable BAR enabled.
+    fn resizable_bar() -> ([vk::MemoryType; 3], [vk::MemoryHeap; 2]) {
+        (
+            [
+                memory_type(DEVICE_LOCAL, 0),
+                memory_type(HOST_VISIBLE, 1),
+                memory_type(BAR, 0),
+            ],
+            [device_local_heap(8192 * MB), host_heap(16384 * MB)],
+        )
+    }
+
+    /// An integrated GPU with a single heap.
+    fn integrated() -> ([vk::MemoryType; 2], [vk::MemoryHeap; 1]) {
+        (
+            [memory_type(DEVICE_LOCAL, 0), memory_type(BAR, 0)],
+            [device_local_heap(4096 * MB)],
+        )
+    }
+
+    #[test]
+    fn small_bar() {
+        let (memory_types, memory_heaps) = discrete();
+        assert_eq!(
+            find_bar_heap(&memory_types, &memory_heaps),
+            Some(BarHeap {
+                heap_index: 2,
+                size: 256 * MB,
+                resizable: false,
+            })
+        );
+    }
+
+    #[test]
+    fn resizable() {
+        let (memory_types, memory_heaps) = resizable_bar();
+        assert_eq!(
+            find_bar_heap(&memory_types, &memory_heaps),
+            Some(BarHeap {
+                heap_index: 0,
+                size: 8192 * MB,
+                resizable: true,
+            })
+        );
+
+        let (memory_types, memory_heaps) = integrated();
+        assert!(find_bar_heap(&memory_types, &memory_heaps).is_some_and(|bar| bar.resizable));
+    }
+
+    #[test]
+    fn no_bar() {
+        let memory_types = [memory_type(DEVICE_LOCAL, 0), memory_type(HOST_VISIBLE, 1)];
+        let memory_heaps = [device_local_heap(8192 * MB), host_heap(16384 * MB)];
+        assert_eq!(find_bar_heap(&memory_types, &memory_heaps), None);
+    }
+
+    #[test]
+    fn unified_memory() {
+        let (memory_types, _) = integrated();
+        assert!(is_unified_memory(
+            vk::PhysicalDeviceType::INTEGRATED_GPU,
+            &memory_types
+        ));
+        // Only some device-local memory types are mappable, which is fine for integrated GPUs.
+        assert!(!is_unified_memory(
+            vk::PhysicalDeviceType::DISCRETE_GPU,
+            &memory_types
+        ));
+
+        // Every device-local memory type is mappable.
+        let memory_types = [memory_type(BAR, 0), memory_type(HOST_VISIBLE, 1)];
+        assert!(is_unified_memory(
+            vk::PhysicalDeviceType::DISCRETE_GPU,
+            &memory_types
+        ));
+    }
+
+    #[test]
+    fn not_unified_memory() {
+        let (memory_types, _) = discrete();
+        assert!(!is_unified_memory(
+            vk::PhysicalDeviceType::DISCRETE_GPU,
+            &memory_types
+        ));
+
+        let (memory_types, _) = resizable_bar();
+        assert!(!is_unified_memory(
+            vk::PhysicalDeviceType::DISCRETE_GPU,
+            &memory_types
+        ));
+
+        // Integrated GPUs without any mappable device-local memory.
+        let memory_types = [memory_type(DEVICE_LOCAL, 0), memory_type(HOST_VISIBLE, 0)];
+        assert!(!is_unified_memory(
+            vk::PhysicalDeviceType::INTEGRATED_GPU,
+            &memory_types
+        ));
+        assert!(!is_unified_memory(
+            vk::PhysicalDeviceType::INTEGRATED_GPU,
+            &[]
+        ));
+    }
+}
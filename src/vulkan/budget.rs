@@ -0,0 +1,384 @@
+use alloc::{string::String, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use ash::vk;
+use log::debug;
+
+use super::Allocator;
+use crate::{AllocationError, BudgetGroupReport, Result};
+
+/// Determines how the [`Allocator`] uses `VK_EXT_memory_budget`.
+///
+/// Any mode other than [`MemoryBudgetMode::Disabled`] requires the `VK_EXT_memory_budget` device
+/// extension to be enabled, and a Vulkan 1.1 instance to query it with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemoryBudgetMode {
+    /// Do not query the driver; [`Allocator::budget()`] reports the size of every heap as its
+    /// budget.
+    #[default]
+    Disabled,
+    /// Query the driver for the usage and budget of every heap in [`Allocator::budget()`].
+    Track,
+    /// Like [`MemoryBudgetMode::Track`], and additionally refuse to create memory blocks that would
+    /// exceed the budget of their heap with [`AllocationError::OutOfMemory`].
+    Enforce,
+}
+
+/// Memory usage and budget of a single memory heap, as returned by [`Allocator::budget()`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapBudget {
+    /// Sum of the sizes of all memory blocks this allocator created in the heap, in bytes.
+    pub block_bytes: u64,
+    /// Sum of the sizes of all live allocations in these memory blocks, in bytes.
+    pub allocation_bytes: u64,
+    /// Estimated memory usage of the heap by the whole process, in bytes.
+    ///
+    /// This is the usage reported by the driver when [`super::AllocatorCreateDesc::memory_budget`]
+    /// is enabled, and [`HeapBudget::block_bytes`] otherwise.
+    pub usage: u64,
+    /// Estimated amount of memory the process can use from the heap, in bytes.
+    ///
+    /// Exceeding the budget may cause the driver to evict memory or fail allocations.  Never more
+    /// than the limit from [`super::AllocatorCreateDesc::heap_size_limits`].
+    pub budget: u64,
+}
+
+/// Handle to a budget group created with [`Allocator::create_budget_group()`].
+///
+/// Allocations are assigned to a budget group through
+/// [`super::AllocationCreateDesc::budget_group`].  The live bytes of all allocations in a group
+/// can never exceed its limit, independently of the memory type or heap they end up in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BudgetGroup {
+    allocator_id: usize,
+    index: usize,
+}
+
+impl BudgetGroup {
+    /// Returns the index of this group among the `group_count` budget groups of the allocator
+    /// with `allocator_id`, or fails with [`AllocationError::UnknownBudgetGroup`] if the group
+    /// was created by another allocator.
+    fn index(self, allocator_id: usize, group_count: usize) -> Result<usize> {
+        if self.allocator_id != allocator_id || self.index >= group_count {
+            return Err(AllocationError::UnknownBudgetGroup);
+        }
+        Ok(self.index)
+    }
+}
+
+/// Returns a new identity for an [`Allocator`], which tells its [`BudgetGroup`]s apart from those
+/// of other allocators.
+pub(super) fn next_allocator_id() -> usize {
+    static NEXT_ALLOCATOR_ID: AtomicUsize = AtomicUsize::new(0);
+    NEXT_ALLOCATOR_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug)]
+pub(super) struct BudgetGroupState {
+    name: String,
+    limit: u64,
+    pub(super) allocated_bytes: u64,
+}
+
+/// Number of allocations after which [`MemoryBudgetMode::Enforce`] queries the driver again.
+/// In between, the blocks this allocator created or destroyed are accounted for by itself.
+const BUDGET_QUERY_INTERVAL: u32 = 30;
+
+/// Driver-reported usage and budget of every heap, together with the block bytes of this
+/// allocator at the time of the query.
+#[derive(Debug)]
+pub(super) struct BudgetSnapshot {
+    heap_usage: [u64; vk::MAX_MEMORY_HEAPS],
+    heap_budget: [u64; vk::MAX_MEMORY_HEAPS],
+    heap_block_bytes: [u64; vk::MAX_MEMORY_HEAPS],
+    /// Number of allocations made since the query.
+    allocations: u32,
+}
+
+impl BudgetSnapshot {
+    fn query(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        heap_block_bytes: [u64; vk::MAX_MEMORY_HEAPS],
+    ) -> Self {
+        let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut memory_properties =
+            vk::PhysicalDeviceMemoryProperties2::default().push_next(&mut budget_properties);
+
+        unsafe {
+            instance.get_physical_device_memory_properties2(physical_device, &mut memory_properties)
+        };
+
+        Self {
+            heap_usage: budget_properties.heap_usage,
+            heap_budget: budget_properties.heap_budget,
+            heap_block_bytes,
+            allocations: 0,
+        }
+    }
+
+    /// Estimated usage of heap `heap_index` now that this allocator has `block_bytes` worth of
+    /// blocks in it: the driver-reported usage plus the blocks created since the query, minus the
+    /// blocks destroyed since.
+    fn usage(&self, heap_index: usize, block_bytes: u64) -> u64 {
+        (self.heap_usage[heap_index] + block_bytes)
+            .saturating_sub(self.heap_block_bytes[heap_index])
+    }
+}
+
+/// Fails with [`AllocationError::OutOfMemory`] if a new block of `size` bytes would not fit in the
+/// budget of heap `heap_index`, where this allocator currently has `block_bytes` worth of blocks.
+pub(super) fn check_heap_budget(
+    snapshot: &BudgetSnapshot,
+    heap_index: usize,
+    block_bytes: u64,
+    size: u64,
+) -> Result<()> {
+    let usage = snapshot.usage(heap_index, block_bytes);
+    let budget = snapshot.heap_budget[heap_index];
+
+    if usage + size > budget {
+        debug!(
+            "Refusing to create a memory block of {} bytes in heap {}: usage {} exceeds budget {}.",
+            size,
+            heap_index,
+            usage + size,
+            budget
+        );
+        return Err(AllocationError::OutOfMemory);
+    }
+
+    Ok(())
+}
+
+impl Allocator {
+    /// Returns the `(block_bytes, allocation_bytes)` of this allocator in heap `heap_index`.
+    pub(super) fn heap_usage(&self, heap_index: usize) -> (u64, u64) {
+        self.memory_types
+            .iter()
+            .filter(|memory_type| memory_type.info.heap_index == heap_index)
+            .flat_map(|memory_type| memory_type.memory_blocks.iter().flatten())
+            .fold((0, 0), |(block_bytes, allocation_bytes), block| {
+                (
+                    block_bytes + block.size,
+                    allocation_bytes + block.sub_allocator.allocated(),
+                )
+            })
+    }
+
+    /// Returns the block bytes of this allocator in every heap.
+    fn heap_block_bytes(&self) -> [u64; vk::MAX_MEMORY_HEAPS] {
+        let mut heap_block_bytes = [0; vk::MAX_MEMORY_HEAPS];
+        for memory_type in &self.memory_types {
+            heap_block_bytes[memory_type.info.heap_index] += memory_type.capacity();
+        }
+        heap_block_bytes
+    }
+
+    /// Prepares the budget snapshot to check a new allocation against, querying the driver again
+    /// if the snapshot is missing or [`BUDGET_QUERY_INTERVAL`] allocations old.
+    pub(super) fn refresh_budget_snapshot(&mut self) {
+        let snapshot = match self.budget_snapshot.take() {
+            Some(mut snapshot) if snapshot.allocations < BUDGET_QUERY_INTERVAL => {
+                snapshot.allocations += 1;
+                snapshot
+            }
+            _ => BudgetSnapshot::query(
+                &self.instance,
+                self.physical_device,
+                self.heap_block_bytes(),
+            ),
+        };
+        self.budget_snapshot = Some(snapshot);
+    }
+
+    /// Returns the usage and budget of every memory heap, indexed by heap index.
+    ///
+    /// The driver is only queried when [`super::AllocatorCreateDesc::memory_budget`] is not
+    /// [`MemoryBudgetMode::Disabled`].
+    pub fn budget(&self) -> Vec<HeapBudget> {
+        let snapshot = (self.memory_budget != MemoryBudgetMode::Disabled).then(|| {
+            BudgetSnapshot::query(
+                &self.instance,
+                self.physical_device,
+                self.heap_block_bytes(),
+            )
+        });
+
+        self.memory_heaps
+            .iter()
+            .enumerate()
+            .map(|(heap_index, heap)| {
+                let (block_bytes, allocation_bytes) = self.heap_usage(heap_index);
+                let (usage, budget) =
+                    snapshot
+                        .as_ref()
+                        .map_or((block_bytes, heap.size), |snapshot| {
+                            (
+                                snapshot.usage(heap_index, block_bytes),
+                                snapshot.heap_budget[heap_index].min(heap.size),
+                            )
+                        });
+
+                HeapBudget {
+                    block_bytes,
+                    allocation_bytes,
+                    usage,
+                    budget,
+                }
+            })
+            .collect()
+    }
+
+    /// Creates a budget group that refuses allocations with
+    /// [`AllocationError::BudgetGroupExceeded`] once the allocations assigned to it would use more
+    /// than `limit` bytes.
+    pub fn create_budget_group(&mut self, name: &str, limit: u64) -> BudgetGroup {
+        self.budget_groups.push(BudgetGroupState {
+            name: name.into(),
+            limit,
+            allocated_bytes: 0,
+        });
+        BudgetGroup {
+            allocator_id: self.allocator_id,
+            index: self.budget_groups.len() - 1,
+        }
+    }
+
+    /// Changes the limit of `budget_group`.  Lowering the limit below the bytes currently
+    /// allocated in the group does not affect existing allocations, but refuses new ones.
+    pub fn set_budget_group_limit(&mut self, budget_group: BudgetGroup, limit: u64) -> Result<()> {
+        self.budget_group_state_mut(budget_group)?.limit = limit;
+        Ok(())
+    }
+
+    pub(super) fn budget_group_state(
+        &self,
+        budget_group: BudgetGroup,
+    ) -> Result<&BudgetGroupState> {
+        let index = budget_group.index(self.allocator_id, self.budget_groups.len())?;
+        Ok(&self.budget_groups[index])
+    }
+
+    pub(super) fn budget_group_state_mut(
+        &mut self,
+        budget_group: BudgetGroup,
+    ) -> Result<&mut BudgetGroupState> {
+        let index = budget_group.index(self.allocator_id, self.budget_groups.len())?;
+        Ok(&mut self.budget_groups[index])
+    }
+
+    /// Fails with [`AllocationError::BudgetGroupExceeded`] if an allocation of `size` bytes would
+    /// not fit in `budget_group`.
+    pub(super) fn check_budget_group(&self, budget_group: BudgetGroup, size: u64) -> Result<()> {
+        let state = self.budget_group_state(budget_group)?;
+
+        if state.allocated_bytes + size > state.limit {
+            debug!(
+                "Refusing allocation of {} bytes in budget group `{}`: {} of {} bytes already allocated.",
+                size, state.name, state.allocated_bytes, state.limit
+            );
+            return Err(AllocationError::BudgetGroupExceeded(state.name.clone()));
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn budget_group_reports(&self) -> Vec<BudgetGroupReport> {
+        self.budget_groups
+            .iter()
+            .map(|state| BudgetGroupReport {
+                name: state.name.clone(),
+                limit: state.limit,
+                allocated_bytes: state.allocated_bytes,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    fn snapshot(usage: u64, budget: u64, block_bytes: u64) -> BudgetSnapshot {
+        let mut snapshot = BudgetSnapshot {
+            heap_usage: [0; vk::MAX_MEMORY_HEAPS],
+            heap_budget: [0; vk::MAX_MEMORY_HEAPS],
+            heap_block_bytes: [0; vk::MAX_MEMORY_HEAPS],
+            allocations: 0,
+        };
+        snapshot.heap_usage[1] = usage;
+        snapshot.heap_budget[1] = budget;
+        snapshot.heap_block_bytes[1] = block_bytes;
+        snapshot
+    }
+
+    #[test]
+    fn fits_in_budget() {
+        let snapshot = snapshot(100 * MB, 256 * MB, 64 * MB);
+
+        assert!(check_heap_budget(&snapshot, 1, 64 * MB, 156 * MB).is_ok());
+        assert!(matches!(
+            check_heap_budget(&snapshot, 1, 64 * MB, 157 * MB),
+            Err(AllocationError::OutOfMemory)
+        ));
+    }
+
+    #[test]
+    fn counts_blocks_created_since_the_query() {
+        // The driver already reported 100MB, including this allocator's 64MB.  Another 64MB block
+        // was created since, which the driver value doesn't include yet.
+        let snapshot = snapshot(100 * MB, 256 * MB, 64 * MB);
+
+        assert_eq!(snapshot.usage(1, 128 * MB), 164 * MB);
+        assert!(check_heap_budget(&snapshot, 1, 128 * MB, 92 * MB).is_ok());
+        assert!(check_heap_budget(&snapshot, 1, 128 * MB, 93 * MB).is_err());
+    }
+
+    #[test]
+    fn counts_blocks_destroyed_since_the_query() {
+        let snapshot = snapshot(100 * MB, 256 * MB, 64 * MB);
+
+        assert_eq!(snapshot.usage(1, 0), 36 * MB);
+        assert!(check_heap_budget(&snapshot, 1, 0, 220 * MB).is_ok());
+    }
+
+    #[test]
+    fn usage_of_other_processes_is_kept() {
+        // Unlike the allocator's own block bytes, memory used elsewhere in the process counts even
+        // when the driver reports more than this allocator created.
+        let snapshot = snapshot(200 * MB, 256 * MB, 0);
+
+        assert_eq!(snapshot.usage(1, 0), 200 * MB);
+        assert!(check_heap_budget(&snapshot, 1, 0, 57 * MB).is_err());
+    }
+
+    #[test]
+    fn heaps_are_independent() {
+        let snapshot = snapshot(256 * MB, 256 * MB, 0);
+
+        assert!(check_heap_budget(&snapshot, 1, 0, 1).is_err());
+        assert!(check_heap_budget(&snapshot, 0, 0, 1).is_err());
+        assert_eq!(snapshot.usage(0, 4 * MB), 4 * MB);
+    }
+
+    #[test]
+    fn budget_groups_of_other_allocators_are_rejected() {
+        let group = BudgetGroup {
+            allocator_id: 3,
+            index: 1,
+        };
+        assert_eq!(group.index(3, 2).unwrap(), 1);
+        assert!(matches!(
+            group.index(4, 2),
+            Err(AllocationError::UnknownBudgetGroup)
+        ));
+        assert!(matches!(
+            group.index(3, 1),
+            Err(AllocationError::UnknownBudgetGroup)
+        ));
+        assert_ne!(next_allocator_id(), next_allocator_id());
+    }
+}
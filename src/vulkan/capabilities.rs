@@ -0,0 +1,310 @@
+use alloc::{ffi::CString, format, vec::Vec};
+use core::ffi::CStr;
+
+use ash::vk;
+
+use super::{Allocator, AllocatorCreateDesc, MemoryBudgetMode};
+use crate::{AllocationError, Result};
+
+/// The API version, device extensions and features the logical device was created with, see
+/// [`AllocatorCreateDesc::device_capabilities`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceCapabilities {
+    /// Vulkan version the device is used with, such as [`vk::API_VERSION_1_1`]: the lower of the
+    /// `apiVersion` of the instance and the version of the physical device.
+    pub api_version: u32,
+    /// Names of the enabled device extensions, such as [`ash::ext::memory_budget::NAME`].
+    pub extensions: Vec<CString>,
+    /// Whether the `bufferDeviceAddress` feature is enabled.
+    pub buffer_device_address: bool,
+    /// Whether the `memoryPriority` feature of `VK_EXT_memory_priority` is enabled.
+    pub memory_priority: bool,
+    /// Whether the `pageableDeviceLocalMemory` feature of `VK_EXT_pageable_device_local_memory` is
+    /// enabled.
+    pub pageable_device_local_memory: bool,
+    /// Whether the `maintenance4` feature is enabled, to query the memory requirements of
+    /// resources before [`Allocator::create_buffer()`] and [`Allocator::create_image()`] create
+    /// them.
+    pub maintenance4: bool,
+}
+
+/// Entry points of `vkGetDevice*MemoryRequirements`, from Vulkan 1.3 or `VK_KHR_maintenance4`.
+pub(super) enum Maintenance4 {
+    Core,
+    Extension(ash::khr::maintenance4::Device),
+}
+
+impl Maintenance4 {
+    /// Loads the entry points if `maintenance4` is enabled in `device_capabilities`.
+    pub(super) fn load(
+        device_capabilities: Option<&DeviceCapabilities>,
+        instance: &ash::Instance,
+        device: &ash::Device,
+    ) -> Option<Self> {
+        let device_capabilities = device_capabilities.filter(|c| c.maintenance4)?;
+        Some(if device_capabilities.api_version >= vk::API_VERSION_1_3 {
+            Self::Core
+        } else {
+            Self::Extension(ash::khr::maintenance4::Device::new(instance, device))
+        })
+    }
+}
+
+/// Entry point of `vkGetBufferDeviceAddress`, from Vulkan 1.2 or `VK_KHR_buffer_device_address`.
+pub(super) enum BufferDeviceAddress {
+    Core,
+    Extension(ash::khr::buffer_device_address::Device),
+}
+
+impl BufferDeviceAddress {
+    /// Loads the entry point for the API version in `device_capabilities`, assuming Vulkan 1.2
+    /// without them.
+    pub(super) fn load(
+        device_capabilities: Option<&DeviceCapabilities>,
+        instance: &ash::Instance,
+        device: &ash::Device,
+    ) -> Self {
+        match device_capabilities {
+            Some(device_capabilities) if device_capabilities.api_version < vk::API_VERSION_1_2 => {
+                Self::Extension(ash::khr::buffer_device_address::Device::new(
+                    instance, device,
+                ))
+            }
+            _ => Self::Core,
+        }
+    }
+
+    pub(super) unsafe fn get_buffer_device_address(
+        &self,
+        device: &ash::Device,
+        info: &vk::BufferDeviceAddressInfo<'_>,
+    ) -> vk::DeviceAddress {
+        match self {
+            Self::Core => device.get_buffer_device_address(info),
+            Self::Extension(device) => device.get_buffer_device_address(info),
+        }
+    }
+}
+
+impl DeviceCapabilities {
+    /// Returns [`true`] if the device extension `name` is enabled.
+    pub fn has_extension(&self, name: &CStr) -> bool {
+        self.extensions
+            .iter()
+            .any(|extension| extension.as_c_str() == name)
+    }
+
+    fn has_version_or_extension(&self, api_version: u32, name: &CStr) -> bool {
+        self.api_version >= api_version || self.has_extension(name)
+    }
+
+    /// Whether `VkMemoryDedicatedAllocateInfo` may be chained, through Vulkan 1.1 or
+    /// `VK_KHR_dedicated_allocation`.
+    pub fn dedicated_allocation(&self) -> bool {
+        self.has_version_or_extension(vk::API_VERSION_1_1, ash::khr::dedicated_allocation::NAME)
+    }
+
+    /// Whether memory can be made exportable, through Vulkan 1.1 or `VK_KHR_external_memory`.
+    pub fn external_memory(&self) -> bool {
+        self.has_version_or_extension(vk::API_VERSION_1_1, ash::khr::external_memory::NAME)
+    }
+
+    /// Whether `VkMemoryAllocateFlagsInfo::deviceMask` may be used, through Vulkan 1.1 or
+    /// `VK_KHR_device_group`.
+    pub fn device_group(&self) -> bool {
+        self.has_version_or_extension(vk::API_VERSION_1_1, ash::khr::device_group::NAME)
+    }
+
+    /// Checks that every enabled feature is provided by the API version or an enabled extension,
+    /// and that `desc` only asks for enabled capabilities.
+    pub(super) fn validate(&self, desc: &AllocatorCreateDesc) -> Result<()> {
+        let features = [
+            (
+                "buffer_device_address",
+                self.buffer_device_address,
+                vk::API_VERSION_1_2,
+                ash::khr::buffer_device_address::NAME,
+            ),
+            (
+                "memory_priority",
+                self.memory_priority,
+                u32::MAX,
+                ash::ext::memory_priority::NAME,
+            ),
+            (
+                "pageable_device_local_memory",
+                self.pageable_device_local_memory,
+                u32::MAX,
+                ash::ext::pageable_device_local_memory::NAME,
+            ),
+            (
+                "maintenance4",
+                self.maintenance4,
+                vk::API_VERSION_1_3,
+                ash::khr::maintenance4::NAME,
+            ),
+        ];
+        for (field, enabled, api_version, extension) in features {
+            if enabled && !self.has_version_or_extension(api_version, extension) {
+                return Err(AllocationError::InvalidAllocatorCreateDesc(format!(
+                    "DeviceCapabilities field `{field}` is set, but neither the API version nor the extensions provide it ({extension:?})."
+                )));
+            }
+        }
+        if self.pageable_device_local_memory && !self.memory_priority {
+            return Err(AllocationError::InvalidAllocatorCreateDesc(
+                "DeviceCapabilities field `pageable_device_local_memory` is set, but `memory_priority`, which it depends on, is not.".into(),
+            ));
+        }
+
+        let requirements = [
+            (
+                "buffer_device_address",
+                desc.buffer_device_address,
+                self.buffer_device_address,
+            ),
+            // The budget is queried with `vkGetPhysicalDeviceMemoryProperties2` from Vulkan 1.1.
+            (
+                "memory_budget",
+                desc.memory_budget != MemoryBudgetMode::Disabled,
+                self.api_version >= vk::API_VERSION_1_1
+                    && self.has_extension(ash::ext::memory_budget::NAME),
+            ),
+            (
+                "memory_priority",
+                desc.memory_priority,
+                self.memory_priority,
+            ),
+            (
+                "pageable_device_local_memory",
+                desc.pageable_device_local_memory,
+                self.pageable_device_local_memory,
+            ),
+            (
+                "physical_device_count",
+                desc.physical_device_count > 1,
+                self.device_group(),
+            ),
+        ];
+        for (field, requested, enabled) in requirements {
+            if requested && !enabled {
+                return Err(AllocationError::InvalidAllocatorCreateDesc(format!(
+                    "AllocatorCreateDesc field `{field}` needs a capability that is missing from `device_capabilities`."
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Allocator {
+    /// Returns the capabilities passed in [`AllocatorCreateDesc::device_capabilities`], if any.
+    pub fn device_capabilities(&self) -> Option<&DeviceCapabilities> {
+        self.device_capabilities.as_ref()
+    }
+
+    /// Whether `VkMemoryDedicatedAllocateInfo` may be chained.  Assumed without capabilities.
+    pub(super) fn supports_dedicated_allocation(&self) -> bool {
+        self.device_capabilities
+            .as_ref()
+            .map_or(true, DeviceCapabilities::dedicated_allocation)
+    }
+
+    /// Whether memory can be made exportable.  Assumed without capabilities.
+    pub(super) fn supports_external_memory(&self) -> bool {
+        self.device_capabilities
+            .as_ref()
+            .map_or(true, DeviceCapabilities::external_memory)
+    }
+
+    /// Whether `vkGetPhysicalDeviceExternalBufferProperties` and
+    /// `vkGetPhysicalDeviceImageFormatProperties2` from Vulkan 1.1 may be called.  Assumed
+    /// without capabilities.
+    pub(super) fn supports_external_memory_properties(&self) -> bool {
+        self.device_capabilities
+            .as_ref()
+            .map_or(true, |capabilities| {
+                capabilities.api_version >= vk::API_VERSION_1_1
+            })
+    }
+
+    /// Whether `vkGet*MemoryRequirements2` from Vulkan 1.1 may be called.  Assumed without
+    /// capabilities.
+    pub(super) fn supports_memory_requirements2(&self) -> bool {
+        self.device_capabilities
+            .as_ref()
+            .map_or(true, |capabilities| {
+                capabilities.api_version >= vk::API_VERSION_1_1
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ptr;
+
+    use super::*;
+
+    fn desc() -> AllocatorCreateDesc {
+        // Nothing is called through these, validation only looks at the other fields.
+        let instance = unsafe { ash::Instance::load_with(|_| ptr::null(), vk::Instance::null()) };
+        let device = unsafe { ash::Device::load_with(|_| ptr::null(), vk::Device::null()) };
+        AllocatorCreateDesc::new(instance, device, vk::PhysicalDevice::null())
+    }
+
+    fn capabilities(api_version: u32, extensions: &[&CStr]) -> DeviceCapabilities {
+        DeviceCapabilities {
+            api_version,
+            extensions: extensions.iter().map(|&name| name.into()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn memory_budget_needs_vulkan_1_1() {
+        let desc = AllocatorCreateDesc {
+            memory_budget: MemoryBudgetMode::Enforce,
+            ..desc()
+        };
+        let extensions = [ash::ext::memory_budget::NAME];
+
+        assert!(matches!(
+            capabilities(vk::API_VERSION_1_0, &extensions).validate(&desc),
+            Err(AllocationError::InvalidAllocatorCreateDesc(_))
+        ));
+        assert!(capabilities(vk::API_VERSION_1_1, &extensions)
+            .validate(&desc)
+            .is_ok());
+        assert!(capabilities(vk::API_VERSION_1_1, &[])
+            .validate(&desc)
+            .is_err());
+
+        let desc = AllocatorCreateDesc {
+            memory_budget: MemoryBudgetMode::Disabled,
+            ..desc
+        };
+        assert!(capabilities(vk::API_VERSION_1_0, &[])
+            .validate(&desc)
+            .is_ok());
+    }
+
+    #[test]
+    fn buffer_device_address_needs_the_feature() {
+        let desc = AllocatorCreateDesc {
+            buffer_device_address: true,
+            ..desc()
+        };
+
+        let mut capabilities = capabilities(
+            vk::API_VERSION_1_1,
+            &[ash::khr::buffer_device_address::NAME],
+        );
+        assert!(capabilities.validate(&desc).is_err());
+        capabilities.buffer_device_address = true;
+        assert!(capabilities.validate(&desc).is_ok());
+
+        capabilities.extensions.clear();
+        assert!(capabilities.validate(&desc).is_err());
+    }
+}
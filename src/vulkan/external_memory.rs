@@ -0,0 +1,331 @@
+#[cfg(feature = "std")]
+use alloc::sync::Arc;
+use core::ffi::c_void;
+#[cfg(feature = "std")]
+use std::backtrace::Backtrace;
+
+use ash::vk;
+use log::debug;
+
+use super::{allocate_device_memory, limits, Allocation, AllocationScheme, Allocator, BlockParams};
+use crate::{
+    allocator::{AllocationType, SubAllocationDesc},
+    AllocationError, MemoryLocation, Result,
+};
+
+/// Describes memory to import with [`Allocator::import_fd()`] or
+/// [`Allocator::import_host_pointer()`].
+#[derive(Clone, Debug)]
+pub struct ImportMemoryDesc<'a> {
+    /// Name of the allocation, for tracking and debugging purposes
+    pub name: &'a str,
+    /// Size of the imported memory, and the requirements of the resource it will be bound to.
+    /// `memory_type_bits` must be restricted to the memory types reported for the handle by
+    /// `vkGetMemoryFdPropertiesKHR` or `vkGetMemoryHostPointerPropertiesEXT`.
+    pub requirements: vk::MemoryRequirements,
+    /// Location used to pick one of the memory types in `requirements.memory_type_bits`
+    pub location: MemoryLocation,
+    /// If the resource is linear (buffer / linear texture) or a regular (tiled) texture.
+    pub linear: bool,
+    /// Some handle types, such as dma-bufs of images, require a dedicated allocation.
+    pub allocation_scheme: AllocationScheme,
+}
+
+impl Allocation {
+    /// Returns the external memory handle types this allocation was made exportable with, see
+    /// [`super::AllocationCreateDesc::export_handle_types`].
+    pub fn export_handle_types(&self) -> vk::ExternalMemoryHandleTypeFlags {
+        self.export_handle_types
+    }
+
+    /// Exports the `VkDeviceMemory` backing this allocation as a POSIX file descriptor with
+    /// `vkGetMemoryFdKHR`, for `handle_type` [`vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD`] or
+    /// [`vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT`].
+    ///
+    /// The file descriptor refers to the whole memory block, which only ever holds this
+    /// allocation.  Ownership of the file descriptor is transferred to the caller.
+    #[cfg(all(feature = "std", unix))]
+    pub fn export_fd(
+        &self,
+        external_memory_fd: &ash::khr::external_memory_fd::Device,
+        handle_type: vk::ExternalMemoryHandleTypeFlags,
+    ) -> Result<std::os::fd::OwnedFd> {
+        use std::os::fd::FromRawFd;
+
+        if self.is_null() || !self.export_handle_types.contains(handle_type) {
+            return Err(AllocationError::ExportFailed(format!(
+                "Allocation was not made exportable as {handle_type:?}"
+            )));
+        }
+
+        let get_fd_info = vk::MemoryGetFdInfoKHR::default()
+            .memory(self.device_memory)
+            .handle_type(handle_type);
+
+        let fd = unsafe { external_memory_fd.get_memory_fd(&get_fd_info) }.map_err(|e| {
+            AllocationError::ExportFailed(format!("vkGetMemoryFdKHR failed: {e:?}"))
+        })?;
+        // SAFETY: `vkGetMemoryFdKHR` returns a new file descriptor that nothing else owns.
+        Ok(unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Returns [`true`] if the memory of this allocation was imported or adopted rather than
+    /// allocated.
+    pub fn is_imported(&self) -> bool {
+        self.imported
+    }
+}
+
+/// Returns every single handle type in `handle_types`.
+fn handle_type_bits(
+    handle_types: vk::ExternalMemoryHandleTypeFlags,
+) -> impl Iterator<Item = vk::ExternalMemoryHandleTypeFlags> {
+    (0..u32::BITS)
+        .map(|bit| vk::ExternalMemoryHandleTypeFlags::from_raw(1 << bit))
+        .filter(move |&handle_type| handle_types.contains(handle_type))
+}
+
+/// Fails with [`AllocationError::ExportFailed`] unless `properties` allow exporting memory as
+/// `handle_type`, and returns whether that memory has to be a dedicated allocation.
+fn check_exportable(
+    handle_type: vk::ExternalMemoryHandleTypeFlags,
+    properties: &vk::ExternalMemoryProperties,
+) -> Result<bool> {
+    let features = properties.external_memory_features;
+    if !features.contains(vk::ExternalMemoryFeatureFlags::EXPORTABLE) {
+        return Err(AllocationError::ExportFailed(format!(
+            "{handle_type:?} is not exportable for this resource"
+        )));
+    }
+    Ok(features.contains(vk::ExternalMemoryFeatureFlags::DEDICATED_ONLY))
+}
+
+impl Allocator {
+    /// Checks that the memory of buffers created with `create_info` can be exported as every one
+    /// of `handle_types`, and returns whether it has to be a dedicated allocation for that.
+    pub(super) fn check_exportable_buffer(
+        &self,
+        create_info: &vk::BufferCreateInfo<'_>,
+        handle_types: vk::ExternalMemoryHandleTypeFlags,
+    ) -> Result<bool> {
+        if !self.supports_external_memory_properties() {
+            return Ok(false);
+        }
+
+        let mut dedicated_only = false;
+        for handle_type in handle_type_bits(handle_types) {
+            let buffer_info = vk::PhysicalDeviceExternalBufferInfo::default()
+                .flags(create_info.flags)
+                .usage(create_info.usage)
+                .handle_type(handle_type);
+            let mut properties = vk::ExternalBufferProperties::default();
+            unsafe {
+                self.instance
+                    .get_physical_device_external_buffer_properties(
+                        self.physical_device,
+                        &buffer_info,
+                        &mut properties,
+                    )
+            };
+            dedicated_only |=
+                check_exportable(handle_type, &properties.external_memory_properties)?;
+        }
+        Ok(dedicated_only)
+    }
+
+    /// Checks that the memory of images created with `create_info` can be exported as every one
+    /// of `handle_types`, and returns whether it has to be a dedicated allocation for that.
+    ///
+    /// Images with [`vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT`] are not checked, since their
+    /// modifier is not known up front.
+    pub(super) fn check_exportable_image(
+        &self,
+        create_info: &vk::ImageCreateInfo<'_>,
+        handle_types: vk::ExternalMemoryHandleTypeFlags,
+    ) -> Result<bool> {
+        if !self.supports_external_memory_properties()
+            || create_info.tiling == vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT
+        {
+            return Ok(false);
+        }
+
+        let mut dedicated_only = false;
+        for handle_type in handle_type_bits(handle_types) {
+            let mut external_info =
+                vk::PhysicalDeviceExternalImageFormatInfo::default().handle_type(handle_type);
+            let format_info = vk::PhysicalDeviceImageFormatInfo2::default()
+                .format(create_info.format)
+                .ty(create_info.image_type)
+                .tiling(create_info.tiling)
+                .usage(create_info.usage)
+                .flags(create_info.flags)
+                .push_next(&mut external_info);
+            let mut external_properties = vk::ExternalImageFormatProperties::default();
+            let mut properties =
+                vk::ImageFormatProperties2::default().push_next(&mut external_properties);
+            unsafe {
+                self.instance.get_physical_device_image_format_properties2(
+                    self.physical_device,
+                    &format_info,
+                    &mut properties,
+                )
+            }
+            .map_err(|e| {
+                AllocationError::ExportFailed(format!(
+                    "{handle_type:?} is not supported for this image: {e:?}"
+                ))
+            })?;
+            dedicated_only |=
+                check_exportable(handle_type, &external_properties.external_memory_properties)?;
+        }
+        Ok(dedicated_only)
+    }
+
+    /// Imports a POSIX file descriptor, such as an opaque FD or a dma-buf, as an allocation with
+    /// its own memory block.  Free it with [`Allocator::free()`] like any other allocation.
+    ///
+    /// `fd` is closed if this fails before the memory is imported.  Once `vkAllocateMemory`
+    /// succeeds, the Vulkan implementation owns the file descriptor and closes it when the memory
+    /// is freed, including when mapping the memory fails right after.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be a valid handle of `handle_type` with at least `desc.requirements.size` bytes.
+    #[cfg(all(feature = "std", unix))]
+    pub unsafe fn import_fd(
+        &mut self,
+        desc: &ImportMemoryDesc<'_>,
+        handle_type: vk::ExternalMemoryHandleTypeFlags,
+        fd: std::os::fd::OwnedFd,
+    ) -> Result<Allocation> {
+        use std::os::fd::{AsRawFd, IntoRawFd};
+
+        let mut import_info = vk::ImportMemoryFdInfoKHR::default()
+            .handle_type(handle_type)
+            .fd(fd.as_raw_fd());
+        let (memory_type_index, memory, params) = self.import_memory(desc, &mut import_info)?;
+        // The file descriptor belongs to the Vulkan implementation now.
+        let _ = fd.into_raw_fd();
+
+        self.allocate_foreign_memory(memory_type_index, memory, desc, params, true)
+    }
+
+    /// Imports host memory with `VK_EXT_external_memory_host`, so the device can access it
+    /// directly, as an allocation with its own memory block.  Free it with [`Allocator::free()`]
+    /// like any other allocation.
+    ///
+    /// # Safety
+    ///
+    /// `host_pointer` must point to `desc.requirements.size` bytes that stay valid until the
+    /// allocation is freed, and both must be aligned to `minImportedHostPointerAlignment`.
+    pub unsafe fn import_host_pointer(
+        &mut self,
+        desc: &ImportMemoryDesc<'_>,
+        handle_type: vk::ExternalMemoryHandleTypeFlags,
+        host_pointer: *mut c_void,
+    ) -> Result<Allocation> {
+        let mut import_info = vk::ImportMemoryHostPointerInfoEXT::default()
+            .handle_type(handle_type)
+            .host_pointer(host_pointer);
+        let (memory_type_index, memory, params) = self.import_memory(desc, &mut import_info)?;
+        self.allocate_foreign_memory(memory_type_index, memory, desc, params, true)
+    }
+
+    /// Allocates the `VkDeviceMemory` that `import_info` imports, and returns it together with
+    /// its memory type index and block parameters.
+    fn import_memory(
+        &mut self,
+        desc: &ImportMemoryDesc<'_>,
+        import_info: &mut dyn vk::ExtendsMemoryAllocateInfo,
+    ) -> Result<(usize, vk::DeviceMemory, BlockParams)> {
+        if self.debug_settings.log_allocations {
+            debug!(
+                "Importing `{}` of {} bytes.",
+                &desc.name, desc.requirements.size
+            );
+        }
+
+        let filter = self.memory_location_preferences.get(desc.location).filter();
+        let memory_type_index = self
+            .find_memorytype_index(desc.requirements.memory_type_bits, filter.required, &filter)
+            .ok_or(AllocationError::NoCompatibleMemoryTypeFound)?
+            as usize;
+
+        let params = BlockParams {
+            allocation_scheme: self.resolve_allocation_scheme(desc.allocation_scheme),
+            export_handle_types: vk::ExternalMemoryHandleTypeFlags::empty(),
+            priority: None,
+            device_mask: 0,
+            buffer_device_address: false,
+        };
+
+        limits::check_allocation_count(
+            self.device_memory_count(),
+            self.max_memory_allocation_count,
+        )?;
+        self.check_allocation_size(desc.requirements.size)?;
+
+        let memory = allocate_device_memory(
+            &self.device,
+            memory_type_index,
+            desc.requirements.size,
+            params,
+            Some(import_info),
+        )?;
+
+        Ok((memory_type_index, memory, params))
+    }
+
+    /// Wraps `memory` of memory type `memory_type_index`, which was not allocated by this
+    /// allocator, in a dedicated block.  If `owned`, `memory` is freed together with the returned
+    /// allocation or if this fails.
+    pub(super) fn allocate_foreign_memory(
+        &mut self,
+        memory_type_index: usize,
+        memory: vk::DeviceMemory,
+        desc: &ImportMemoryDesc<'_>,
+        params: BlockParams,
+        owned: bool,
+    ) -> Result<Allocation> {
+        let memory_type = &mut self.memory_types[memory_type_index];
+        let sub_allocation = memory_type.allocate_imported(
+            &self.device,
+            memory,
+            &SubAllocationDesc {
+                name: desc.name,
+                size: desc.requirements.size,
+                alignment: desc.requirements.alignment,
+                allocation_type: if desc.linear {
+                    AllocationType::Linear
+                } else {
+                    AllocationType::NonLinear
+                },
+                granularity: self.buffer_image_granularity,
+                dedicated: true,
+                block_params: params,
+                #[cfg(feature = "std")]
+                backtrace: Arc::new(if self.debug_settings.store_stack_traces {
+                    Backtrace::force_capture()
+                } else {
+                    Backtrace::disabled()
+                }),
+            },
+            owned,
+        )?;
+
+        Ok(Allocation {
+            chunk_id: Some(sub_allocation.chunk_id),
+            offset: sub_allocation.offset,
+            size: desc.requirements.size,
+            memory_block_index: sub_allocation.memory_block_index,
+            memory_type_index,
+            device_memory: sub_allocation.memory,
+            mapped_ptr: sub_allocation.mapped_ptr,
+            memory_properties: memory_type.info.memory_properties,
+            name: Some(desc.name.into()),
+            dedicated_allocation: true,
+            imported: true,
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,73 @@
+use ash::vk;
+use log::debug;
+
+use super::Allocator;
+use crate::{AllocationError, AllocationSizes, Result};
+
+/// Returns `maxMemoryAllocationSize` of `VkPhysicalDeviceMaintenance3Properties`, or
+/// [`u64::MAX`] if the driver leaves it at `0`.
+pub(super) fn max_memory_allocation_size(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> u64 {
+    let mut maintenance3_properties = vk::PhysicalDeviceMaintenance3Properties::default();
+    let mut properties =
+        vk::PhysicalDeviceProperties2::default().push_next(&mut maintenance3_properties);
+    unsafe { instance.get_physical_device_properties2(physical_device, &mut properties) };
+    match maintenance3_properties.max_memory_allocation_size {
+        0 => u64::MAX,
+        max_memory_allocation_size => max_memory_allocation_size,
+    }
+}
+
+/// Fails if another `VkDeviceMemory` on top of `device_memory_count` would exceed `max_count`, the
+/// `maxMemoryAllocationCount` of the device.
+pub(super) fn check_allocation_count(device_memory_count: usize, max_count: u32) -> Result<()> {
+    if device_memory_count >= max_count as usize {
+        debug!(
+            "Refusing to allocate device memory: reached maxMemoryAllocationCount of {max_count}."
+        );
+        return Err(AllocationError::AllocationCountExceeded(max_count));
+    }
+    Ok(())
+}
+
+impl Allocator {
+    /// Returns the number of live `VkDeviceMemory` objects, including imported and adopted ones,
+    /// which the driver caps at `maxMemoryAllocationCount`.
+    pub fn device_memory_count(&self) -> usize {
+        self.memory_types
+            .iter()
+            .map(|memory_type| memory_type.block_count)
+            .sum()
+    }
+
+    /// Whether three quarters of `maxMemoryAllocationCount` are used up.  From then on, memory
+    /// blocks grow larger and dedicated allocations that drivers merely prefer are sub-allocated.
+    pub(super) fn is_near_allocation_count_limit(&self) -> bool {
+        let max_count = self.max_memory_allocation_count as usize;
+        self.device_memory_count() >= max_count - max_count / 4
+    }
+
+    /// Fails if a single `VkDeviceMemory` of `size` bytes would exceed `maxMemoryAllocationSize`.
+    pub(super) fn check_allocation_size(&self, size: u64) -> Result<()> {
+        if size > self.max_memory_allocation_size {
+            return Err(AllocationError::AllocationSizeExceeded(
+                size,
+                self.max_memory_allocation_size,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the block sizes to use for new memory blocks, within `maxMemoryAllocationSize`.
+    pub(super) fn memory_block_sizes(&self) -> AllocationSizes {
+        let scale = if self.is_near_allocation_count_limit() {
+            4
+        } else {
+            1
+        };
+        self.allocation_sizes
+            .scaled(scale, self.max_memory_allocation_size)
+    }
+}
@@ -0,0 +1,315 @@
+#[cfg(feature = "std")]
+use core::ops::Range;
+use core::{
+    ffi::c_void,
+    mem::{align_of, size_of},
+    ptr::NonNull,
+};
+#[cfg(feature = "std")]
+use std::io;
+
+use ash::vk;
+#[cfg(feature = "std")]
+use log::warn;
+
+use super::{Allocation, Allocator};
+use crate::{AllocationError, Result};
+
+/// Returns `mapped_ptr` advanced by `offset` bytes.
+pub(super) fn offset_ptr(mapped_ptr: NonNull<c_void>, offset: u64) -> NonNull<c_void> {
+    // SAFETY: `offset` lies within the mapped memory block, which never wraps around.
+    unsafe { NonNull::new_unchecked(mapped_ptr.as_ptr().cast::<u8>().add(offset as usize).cast()) }
+}
+
+impl Allocator {
+    /// Returns a pointer to the start of `allocation` in host memory, if its memory is mapped
+    /// persistently or `allocation` was mapped through [`Allocator::map()`].
+    pub fn host_ptr(&self, allocation: &Allocation) -> Option<NonNull<c_void>> {
+        if allocation.is_null() {
+            return None;
+        }
+
+        // Mappings of other allocations in the same block may be released at any time.
+        allocation.mapped_ptr().or_else(|| {
+            if allocation.map_count == 0 {
+                return None;
+            }
+            self.memory_types[allocation.memory_type_index]
+                .mapped_ptr(allocation.memory_block_index)
+                .map(|mapped_ptr| offset_ptr(mapped_ptr, allocation.offset))
+        })
+    }
+
+    /// Returns a pointer to `len` elements of `T` at `offset` bytes into the mapped memory of
+    /// `allocation`, after checking that they are in bounds and properly aligned.
+    fn typed_ptr<T>(&self, allocation: &Allocation, offset: u64, len: usize) -> Result<*mut T> {
+        let mapped_ptr = self
+            .host_ptr(allocation)
+            .ok_or_else(|| AllocationError::FailedToMap("Allocation is not mapped".into()))?;
+
+        let end = (size_of::<T>() as u64)
+            .checked_mul(len as u64)
+            .and_then(|size| offset.checked_add(size))
+            .unwrap_or(u64::MAX);
+        if end > allocation.size {
+            return Err(AllocationError::InvalidRange(offset..end));
+        }
+
+        let ptr = offset_ptr(mapped_ptr, offset).as_ptr().cast::<T>();
+        let alignment = align_of::<T>();
+        if ptr as usize % alignment != 0 {
+            return Err(AllocationError::MisalignedOffset(offset, alignment as u64));
+        }
+
+        Ok(ptr)
+    }
+
+    /// Copies `data` into the mapped memory of `allocation`, `offset` bytes from its start.
+    ///
+    /// Fails if the allocation is neither persistently mapped nor mapped through
+    /// [`Allocator::map()`], if `data` does not fit, or if the destination is not aligned for `T`.
+    /// Non-coherent memory still has to be flushed with [`Allocator::flush()`] afterwards.
+    pub fn write_slice<T: Copy>(
+        &self,
+        allocation: &mut Allocation,
+        offset: u64,
+        data: &[T],
+    ) -> Result<()> {
+        let dst = self.typed_ptr::<T>(allocation, offset, data.len())?;
+        // SAFETY: The destination is in bounds, aligned, and exclusively borrowed through
+        // `allocation`.
+        unsafe { core::ptr::copy_nonoverlapping(data.as_ptr(), dst, data.len()) };
+        Ok(())
+    }
+
+    /// Copies `data.len()` elements from the mapped memory of `allocation`, `offset` bytes from its
+    /// start, into `data`.
+    ///
+    /// Fails if the allocation is neither persistently mapped nor mapped through
+    /// [`Allocator::map()`], if the source is out of bounds, or if it is not aligned for `T`.
+    /// Non-coherent memory has to be invalidated with [`Allocator::invalidate()`] beforehand.
+    #[cfg(feature = "bytemuck")]
+    pub fn read_slice<T: bytemuck::AnyBitPattern>(
+        &self,
+        allocation: &Allocation,
+        offset: u64,
+        data: &mut [T],
+    ) -> Result<()> {
+        // SAFETY: Any bit pattern is a valid `T`.
+        unsafe { self.read_slice_unchecked(allocation, offset, data) }
+    }
+
+    /// Copies `data.len()` elements from the mapped memory of `allocation`, `offset` bytes from its
+    /// start, into `data`, for types that are not known to be valid for any bit pattern.
+    ///
+    /// Fails like `Allocator::read_slice()`, which is available with the `bytemuck` feature.
+    ///
+    /// # Safety
+    ///
+    /// The source memory must contain valid values of `T`, such as data written by the device in
+    /// the layout of `T`.  This is always the case for plain integer and floating point types.
+    pub unsafe fn read_slice_unchecked<T: Copy>(
+        &self,
+        allocation: &Allocation,
+        offset: u64,
+        data: &mut [T],
+    ) -> Result<()> {
+        let src = self.typed_ptr::<T>(allocation, offset, data.len())?;
+        core::ptr::copy_nonoverlapping(src, data.as_mut_ptr(), data.len());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+fn io_error(error: AllocationError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, error)
+}
+
+/// A cursor over the mapped memory of an [`Allocation`] that implements [`io::Read`],
+/// [`io::Write`] and [`io::Seek`], as returned by [`Allocator::cursor()`].
+///
+/// Everything written through the cursor is flushed when it is dropped, and non-coherent memory
+/// is invalidated before the first read.
+#[cfg(feature = "std")]
+pub struct MappedAllocationCursor<'a> {
+    allocator: &'a Allocator,
+    allocation: &'a mut Allocation,
+    position: u64,
+    written: Option<Range<u64>>,
+    invalidated: bool,
+}
+
+#[cfg(feature = "std")]
+impl MappedAllocationCursor<'_> {
+    /// Returns the current position of the cursor, in bytes from the start of the allocation.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Flushes everything written so far, reporting failures unlike dropping the cursor.
+    pub fn flush_writes(&mut self) -> Result<()> {
+        match self.written.take() {
+            Some(written) => self.allocator.flush(self.allocation, written),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the number of bytes between the cursor and the end of the allocation, which is
+    /// zero after seeking past the end.
+    fn remaining(&self) -> usize {
+        usize::try_from(self.allocation.size.saturating_sub(self.position)).unwrap_or(usize::MAX)
+    }
+}
+
+#[cfg(feature = "std")]
+impl io::Write for MappedAllocationCursor<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.remaining());
+        if len == 0 {
+            return Ok(0);
+        }
+        let start = self.position;
+        self.allocator
+            .write_slice(self.allocation, start, &buf[..len])
+            .map_err(io_error)?;
+
+        self.position += len as u64;
+        self.written = Some(match self.written.take() {
+            Some(written) => written.start.min(start)..written.end.max(self.position),
+            None => start..self.position,
+        });
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_writes().map_err(io_error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl io::Read for MappedAllocationCursor<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.remaining());
+        if len == 0 {
+            return Ok(0);
+        }
+
+        if !self.invalidated {
+            self.allocator
+                .invalidate(self.allocation, 0..self.allocation.size)
+                .map_err(io_error)?;
+            self.invalidated = true;
+        }
+
+        // SAFETY: Every byte is a valid `u8`.
+        unsafe {
+            self.allocator
+                .read_slice_unchecked(self.allocation, self.position, &mut buf[..len])
+        }
+        .map_err(io_error)?;
+
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+#[cfg(feature = "std")]
+impl io::Seek for MappedAllocationCursor<'_> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            io::SeekFrom::Start(offset) => Some(offset),
+            io::SeekFrom::End(offset) => self.allocation.size.checked_add_signed(offset),
+            io::SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot seek before the start of the allocation",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for MappedAllocationCursor<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush_writes() {
+            warn!("Failed to flush writes to mapped allocation: {e}");
+        }
+    }
+}
+
+impl Allocator {
+    /// Maps the memory of `allocation` and returns a pointer to its start.
+    ///
+    /// Mappings are reference-counted per memory block: with
+    /// [`super::AllocatorCreateDesc::map_on_demand`], a block is mapped by the first call and
+    /// unmapped once every call has been balanced by [`Allocator::unmap()`].  Without it, this
+    /// returns the persistent mapping of the block, which is never unmapped before it is freed.
+    /// Every allocation also counts its own mappings, and releases any it still holds when it is
+    /// freed.
+    pub fn map(&mut self, allocation: &mut Allocation) -> Result<NonNull<c_void>> {
+        if allocation.is_null() {
+            return Err(AllocationError::FailedToMap(
+                "Cannot map a null allocation".into(),
+            ));
+        }
+        if allocation
+            .memory_properties
+            .contains(vk::MemoryPropertyFlags::PROTECTED)
+        {
+            return Err(AllocationError::FailedToMap(
+                "Protected memory cannot be mapped".into(),
+            ));
+        }
+
+        let mapped_ptr = self.memory_types[allocation.memory_type_index].map_allocation(
+            &self.device,
+            allocation.memory_block_index,
+            &mut allocation.map_count,
+        )?;
+
+        Ok(offset_ptr(mapped_ptr, allocation.offset))
+    }
+
+    /// Releases a mapping of `allocation` obtained with [`Allocator::map()`].  Pointers returned
+    /// by it must not be used anymore afterwards.  Fails with [`AllocationError::FailedToMap`] if
+    /// `allocation` holds no such mapping, even if another allocation in the same memory block
+    /// does.
+    pub fn unmap(&mut self, allocation: &mut Allocation) -> Result<()> {
+        if allocation.is_null() {
+            return Ok(());
+        }
+
+        self.memory_types[allocation.memory_type_index].unmap_allocation(
+            &self.device,
+            allocation.memory_block_index,
+            &mut allocation.map_count,
+        )
+    }
+
+    /// Returns a cursor to stream data into or out of the mapped memory of `allocation`, for
+    /// example straight from a file with [`io::copy()`].  The allocation has to be persistently
+    /// mapped or mapped through [`Allocator::map()`] for as long as the cursor is used.
+    #[cfg(feature = "std")]
+    pub fn cursor<'a>(
+        &'a self,
+        allocation: &'a mut Allocation,
+    ) -> Result<MappedAllocationCursor<'a>> {
+        if self.host_ptr(allocation).is_none() {
+            return Err(AllocationError::FailedToMap(
+                "Allocation is not mapped".into(),
+            ));
+        }
+
+        Ok(MappedAllocationCursor {
+            allocator: self,
+            allocation,
+            position: 0,
+            written: None,
+            invalidated: false,
+        })
+    }
+}
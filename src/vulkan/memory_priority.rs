@@ -0,0 +1,68 @@
+use super::{Allocation, Allocator};
+use crate::{AllocationError, Result};
+
+/// Returns the priority to store in [`super::BlockParams`] for a requested `priority`.  The default
+/// priority of `0.5` maps to [`None`], so that allocations asking for it explicitly share memory
+/// blocks with those that don't ask for a priority at all.
+pub(super) fn block_priority(priority: Option<f32>) -> Option<f32> {
+    priority.filter(|&priority| priority != 0.5)
+}
+
+impl Allocator {
+    /// Changes the priority of the memory behind `allocation` with `vkSetDeviceMemoryPriorityEXT`,
+    /// for example to let the driver demote streaming textures before render targets.
+    ///
+    /// Needs [`super::AllocatorCreateDesc::pageable_device_local_memory`], and an allocation with
+    /// a memory block of its own, such as one with a dedicated [`super::AllocationScheme`], since
+    /// the priority applies to the whole `VkDeviceMemory`.  Fails with
+    /// [`AllocationError::InvalidAllocationCreateDesc`] otherwise, or if `priority` is not within
+    /// `0.0..=1.0`.
+    pub fn set_memory_priority(&mut self, allocation: &Allocation, priority: f32) -> Result<()> {
+        let Some(pageable_device_local_memory) = &self.pageable_device_local_memory else {
+            return Err(AllocationError::InvalidAllocationCreateDesc);
+        };
+
+        if allocation.is_null() || !(0.0..=1.0).contains(&priority) {
+            return Err(AllocationError::InvalidAllocationCreateDesc);
+        }
+
+        let block = self.memory_types[allocation.memory_type_index].memory_blocks
+            [allocation.memory_block_index]
+            .as_mut()
+            .ok_or_else(|| AllocationError::Internal("Memory block must be Some.".into()))?;
+
+        if block.sub_allocator.supports_general_allocations() {
+            return Err(AllocationError::InvalidAllocationCreateDesc);
+        }
+
+        unsafe {
+            (pageable_device_local_memory
+                .fp()
+                .set_device_memory_priority_ext)(
+                self.device.handle(),
+                allocation.device_memory,
+                priority,
+            )
+        };
+
+        // Keep the block parameters in sync, even though dedicated blocks are never shared.
+        if self.memory_priority {
+            block.params.priority = block_priority(Some(priority));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::block_priority;
+
+    #[test]
+    fn default_priority_is_normalized() {
+        assert_eq!(block_priority(None), None);
+        assert_eq!(block_priority(Some(0.5)), None);
+        assert_eq!(block_priority(Some(0.0)), Some(0.0));
+        assert_eq!(block_priority(Some(1.0)), Some(1.0));
+    }
+}
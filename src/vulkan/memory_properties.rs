@@ -0,0 +1,347 @@
+use alloc::{vec, vec::Vec};
+
+use ash::vk;
+
+use super::BarHeap;
+use crate::MemoryLocation;
+
+/// Memory property flags the [`super::Allocator`] looks for when picking a memory type for an
+/// allocation.
+///
+/// Out of the memory types in [`Self::memory_type_mask`] with all [`Self::required`] flags, the
+/// allocator picks the one with the lowest cost: every missing [`Self::preferred`] flag and every
+/// present [`Self::not_preferred`] flag adds one, and ties go to the lowest memory type index.
+/// When allocating from that memory type fails, the other memory types with all required flags
+/// are tried in order of cost.  After that, the required flags are replaced with every set of
+/// flags in [`Self::fallbacks`] in order.
+///
+/// Memory types with [`vk::MemoryPropertyFlags::PROTECTED`],
+/// [`vk::MemoryPropertyFlags::DEVICE_COHERENT_AMD`] or
+/// [`vk::MemoryPropertyFlags::DEVICE_UNCACHED_AMD`] are never picked unless those flags are
+/// required, since they can only be used by resources or devices that opted into them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryPropertyPreferences {
+    pub required: vk::MemoryPropertyFlags,
+    pub preferred: vk::MemoryPropertyFlags,
+    pub not_preferred: vk::MemoryPropertyFlags,
+    /// Bit mask of memory type indices that may be used, on top of
+    /// [`vk::MemoryRequirements::memory_type_bits`].
+    pub memory_type_mask: u32,
+    pub fallbacks: Vec<vk::MemoryPropertyFlags>,
+}
+
+impl MemoryPropertyPreferences {
+    /// Returns the preferences the allocator uses for `location` by default.
+    pub fn for_location(location: MemoryLocation) -> Self {
+        let (required, preferred, fallbacks) = match location {
+            MemoryLocation::GpuOnly => (
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                vec![],
+            ),
+            MemoryLocation::CpuToGpu => (
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                vk::MemoryPropertyFlags::HOST_VISIBLE
+                    | vk::MemoryPropertyFlags::HOST_COHERENT
+                    | vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                // Non-coherent memory still works for uploads, as long as they are flushed.
+                vec![vk::MemoryPropertyFlags::HOST_VISIBLE],
+            ),
+            MemoryLocation::GpuToCpu => (
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                vk::MemoryPropertyFlags::HOST_VISIBLE
+                    | vk::MemoryPropertyFlags::HOST_COHERENT
+                    | vk::MemoryPropertyFlags::HOST_CACHED,
+                vec![],
+            ),
+            MemoryLocation::Unknown => (
+                vk::MemoryPropertyFlags::empty(),
+                vk::MemoryPropertyFlags::empty(),
+                vec![],
+            ),
+        };
+
+        Self {
+            required,
+            preferred,
+            // Lazily allocated memory is only meant for transient attachments.
+            not_preferred: vk::MemoryPropertyFlags::LAZILY_ALLOCATED,
+            memory_type_mask: !0,
+            fallbacks,
+        }
+    }
+
+    /// Returns preferences for transient attachments, which may never be backed by physical memory
+    /// on tile-based GPUs.  These pick [`vk::MemoryPropertyFlags::LAZILY_ALLOCATED`] memory, and
+    /// fall back to any device-local memory if there is none.  Allocations in lazily allocated
+    /// memory are always dedicated.
+    ///
+    /// ```
+    /// # use gpu_allocator::vulkan::{AllocationCreateDesc, MemoryPropertyPreferences};
+    /// # use gpu_allocator::MemoryLocation;
+    /// let transient = MemoryPropertyPreferences::lazily_allocated();
+    /// let desc = AllocationCreateDesc {
+    ///     name: "G-buffer",
+    ///     location: MemoryLocation::GpuOnly,
+    ///     memory_properties: Some(&transient),
+    ///     ..Default::default()
+    /// };
+    /// ```
+    pub fn lazily_allocated() -> Self {
+        Self {
+            required: vk::MemoryPropertyFlags::DEVICE_LOCAL
+                | vk::MemoryPropertyFlags::LAZILY_ALLOCATED,
+            preferred: vk::MemoryPropertyFlags::DEVICE_LOCAL
+                | vk::MemoryPropertyFlags::LAZILY_ALLOCATED,
+            not_preferred: vk::MemoryPropertyFlags::empty(),
+            memory_type_mask: !0,
+            fallbacks: vec![vk::MemoryPropertyFlags::DEVICE_LOCAL],
+        }
+    }
+
+    /// Returns preferences for device-local memory that the host can write to directly when that
+    /// is cheap, given the [`Allocator::bar_heap()`] of the device.  With Resizable BAR or on
+    /// integrated GPUs, these pick mappable device-local memory, and fall back to any device-local
+    /// memory if that runs out.  A small BAR heap is left alone, since it is best kept for data
+    /// that benefits most, so these are plain [`MemoryLocation::GpuOnly`] preferences then.
+    ///
+    /// Either way, check [`Allocation::mapped_ptr()`] to find out whether the allocation can be
+    /// written to directly or has to be uploaded to through a staging buffer.
+    ///
+    /// [`Allocator::bar_heap()`]: super::Allocator::bar_heap()
+    /// [`Allocation::mapped_ptr()`]: super::Allocation::mapped_ptr()
+    pub fn prefer_mappable(bar_heap: Option<BarHeap>) -> Self {
+        if !bar_heap.is_some_and(|bar_heap| bar_heap.resizable) {
+            let mut preferences = Self::for_location(MemoryLocation::GpuOnly);
+            preferences.not_preferred |= vk::MemoryPropertyFlags::HOST_VISIBLE;
+            return preferences;
+        }
+
+        Self {
+            required: vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::HOST_VISIBLE,
+            preferred: vk::MemoryPropertyFlags::DEVICE_LOCAL
+                | vk::MemoryPropertyFlags::HOST_VISIBLE
+                | vk::MemoryPropertyFlags::HOST_COHERENT,
+            not_preferred: vk::MemoryPropertyFlags::LAZILY_ALLOCATED,
+            memory_type_mask: !0,
+            fallbacks: vec![vk::MemoryPropertyFlags::DEVICE_LOCAL],
+        }
+    }
+
+    /// Returns the flags to pick memory types by, before the allocator adjusts them for a single
+    /// allocation.
+    pub(super) fn filter(&self) -> MemoryTypeFilter {
+        MemoryTypeFilter {
+            required: self.required,
+            preferred: self.preferred,
+            not_preferred: self.not_preferred,
+            memory_type_mask: self.memory_type_mask,
+            fallback_required: vk::MemoryPropertyFlags::empty(),
+        }
+    }
+}
+
+/// The flags of a [`MemoryPropertyPreferences`] that a single allocation picks its memory type by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct MemoryTypeFilter {
+    pub(super) required: vk::MemoryPropertyFlags,
+    pub(super) preferred: vk::MemoryPropertyFlags,
+    pub(super) not_preferred: vk::MemoryPropertyFlags,
+    pub(super) memory_type_mask: u32,
+    /// Flags that are added to every one of the [`MemoryPropertyPreferences::fallbacks`].
+    pub(super) fallback_required: vk::MemoryPropertyFlags,
+}
+
+impl MemoryTypeFilter {
+    /// Adds `flags` to the required flags, including those of every fallback, and prefers them.
+    pub(super) fn require(&mut self, flags: vk::MemoryPropertyFlags) {
+        self.required |= flags;
+        self.preferred |= flags;
+        self.fallback_required |= flags;
+    }
+
+    /// Returns the cost of a memory type with `flags`, lower is better.
+    pub(super) fn cost(&self, flags: vk::MemoryPropertyFlags) -> u32 {
+        (self.preferred & !flags).as_raw().count_ones()
+            + (self.not_preferred & flags).as_raw().count_ones()
+    }
+}
+
+/// The [`MemoryPropertyPreferences`] of every [`MemoryLocation`].
+///
+/// For example, to let [`MemoryLocation::GpuOnly`] allocations spill to system memory once
+/// device-local memory is exhausted:
+///
+/// ```
+/// # use ash::vk;
+/// # use gpu_allocator::vulkan::MemoryLocationPreferences;
+/// let mut preferences = MemoryLocationPreferences::default();
+/// preferences.gpu_only.fallbacks.push(vk::MemoryPropertyFlags::empty());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryLocationPreferences {
+    pub unknown: MemoryPropertyPreferences,
+    pub gpu_only: MemoryPropertyPreferences,
+    pub cpu_to_gpu: MemoryPropertyPreferences,
+    pub gpu_to_cpu: MemoryPropertyPreferences,
+}
+
+impl MemoryLocationPreferences {
+    /// Returns the preferences for `location`.
+    pub fn get(&self, location: MemoryLocation) -> &MemoryPropertyPreferences {
+        match location {
+            MemoryLocation::Unknown => &self.unknown,
+            MemoryLocation::GpuOnly => &self.gpu_only,
+            MemoryLocation::CpuToGpu => &self.cpu_to_gpu,
+            MemoryLocation::GpuToCpu => &self.gpu_to_cpu,
+        }
+    }
+}
+
+impl Default for MemoryLocationPreferences {
+    fn default() -> Self {
+        Self {
+            unknown: MemoryPropertyPreferences::for_location(MemoryLocation::Unknown),
+            gpu_only: MemoryPropertyPreferences::for_location(MemoryLocation::GpuOnly),
+            cpu_to_gpu: MemoryPropertyPreferences::for_location(MemoryLocation::CpuToGpu),
+            gpu_to_cpu: MemoryPropertyPreferences::for_location(MemoryLocation::GpuToCpu),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICE_LOCAL: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::DEVICE_LOCAL;
+    const HOST_VISIBLE: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::HOST_VISIBLE;
+    const HOST_COHERENT: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::HOST_COHERENT;
+    const HOST_CACHED: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::HOST_CACHED;
+
+    const RESIZABLE_BAR: BarHeap = BarHeap {
+        heap_index: 0,
+        size: 8 << 30,
+        resizable: true,
+    };
+    const SMALL_BAR: BarHeap = BarHeap {
+        heap_index: 2,
+        size: 256 << 20,
+        resizable: false,
+    };
+
+    #[test]
+    fn cost_counts_missing_preferred_flags() {
+        let filter = MemoryPropertyPreferences::for_location(MemoryLocation::CpuToGpu).filter();
+
+        assert_eq!(filter.cost(DEVICE_LOCAL | HOST_VISIBLE | HOST_COHERENT), 0);
+        assert_eq!(filter.cost(HOST_VISIBLE | HOST_COHERENT), 1);
+        assert_eq!(filter.cost(HOST_VISIBLE | HOST_COHERENT | HOST_CACHED), 1);
+        assert_eq!(filter.cost(vk::MemoryPropertyFlags::empty()), 3);
+    }
+
+    #[test]
+    fn cost_counts_present_not_preferred_flags() {
+        let filter = MemoryPropertyPreferences::for_location(MemoryLocation::GpuOnly).filter();
+
+        assert_eq!(filter.cost(DEVICE_LOCAL), 0);
+        assert_eq!(filter.cost(DEVICE_LOCAL | HOST_VISIBLE), 0);
+        assert_eq!(
+            filter.cost(DEVICE_LOCAL | vk::MemoryPropertyFlags::LAZILY_ALLOCATED),
+            1
+        );
+        assert_eq!(filter.cost(vk::MemoryPropertyFlags::LAZILY_ALLOCATED), 2);
+        // A missing preferred flag weighs as much as a present not-preferred one.
+        assert_eq!(
+            filter.cost(vk::MemoryPropertyFlags::empty()),
+            filter.cost(DEVICE_LOCAL | vk::MemoryPropertyFlags::LAZILY_ALLOCATED)
+        );
+    }
+
+    #[test]
+    fn lazily_allocated_is_only_preferred_by_its_preset() {
+        let lazy = vk::MemoryPropertyFlags::LAZILY_ALLOCATED;
+
+        let filter = MemoryPropertyPreferences::lazily_allocated().filter();
+        assert!(filter.cost(DEVICE_LOCAL | lazy) < filter.cost(DEVICE_LOCAL));
+
+        let filter = MemoryPropertyPreferences::for_location(MemoryLocation::Unknown).filter();
+        assert!(filter.cost(DEVICE_LOCAL | lazy) > filter.cost(DEVICE_LOCAL));
+    }
+
+    #[test]
+    fn prefer_mappable_only_picks_a_resizable_bar() {
+        let bar = DEVICE_LOCAL | HOST_VISIBLE | HOST_COHERENT;
+
+        let preferences = MemoryPropertyPreferences::prefer_mappable(Some(RESIZABLE_BAR));
+        assert_eq!(preferences.required, DEVICE_LOCAL | HOST_VISIBLE);
+        assert_eq!(preferences.fallbacks, [DEVICE_LOCAL]);
+        let filter = preferences.filter();
+        assert!(filter.cost(bar) < filter.cost(DEVICE_LOCAL | HOST_VISIBLE));
+
+        for bar_heap in [Some(SMALL_BAR), None] {
+            let filter = MemoryPropertyPreferences::prefer_mappable(bar_heap).filter();
+            assert_eq!(filter.required, DEVICE_LOCAL);
+            // A small BAR type must not tie with plain device-local types.
+            assert!(filter.cost(DEVICE_LOCAL) < filter.cost(bar));
+        }
+    }
+
+    #[test]
+    fn require_applies_to_fallbacks() {
+        let preferences = MemoryPropertyPreferences::for_location(MemoryLocation::GpuOnly);
+        let mut filter = preferences.filter();
+        assert_eq!(filter.required, preferences.required);
+        assert_eq!(filter.fallback_required, vk::MemoryPropertyFlags::empty());
+
+        filter.require(vk::MemoryPropertyFlags::PROTECTED);
+
+        assert_eq!(
+            filter.required,
+            DEVICE_LOCAL | vk::MemoryPropertyFlags::PROTECTED
+        );
+        assert_eq!(filter.fallback_required, vk::MemoryPropertyFlags::PROTECTED);
+        assert_eq!(
+            filter.cost(DEVICE_LOCAL),
+            filter.cost(DEVICE_LOCAL | vk::MemoryPropertyFlags::PROTECTED) + 1
+        );
+    }
+
+    #[test]
+    fn default_fallbacks_relax_the_required_flags() {
+        let locations = [
+            MemoryLocation::Unknown,
+            MemoryLocation::GpuOnly,
+            MemoryLocation::CpuToGpu,
+            MemoryLocation::GpuToCpu,
+        ];
+        let presets = [
+            MemoryPropertyPreferences::lazily_allocated(),
+            MemoryPropertyPreferences::prefer_mappable(Some(RESIZABLE_BAR)),
+            MemoryPropertyPreferences::prefer_mappable(Some(SMALL_BAR)),
+            MemoryPropertyPreferences::prefer_mappable(None),
+        ];
+
+        for preferences in locations
+            .into_iter()
+            .map(MemoryPropertyPreferences::for_location)
+            .chain(presets)
+        {
+            for &fallback in &preferences.fallbacks {
+                assert!(
+                    preferences.required.contains(fallback) && preferences.required != fallback,
+                    "{:?} falls back to {fallback:?}",
+                    preferences.required
+                );
+            }
+        }
+
+        assert_eq!(
+            MemoryPropertyPreferences::for_location(MemoryLocation::CpuToGpu).fallbacks,
+            [HOST_VISIBLE]
+        );
+        assert_eq!(
+            MemoryPropertyPreferences::lazily_allocated().fallbacks,
+            [DEVICE_LOCAL]
+        );
+    }
+}
@@ -8,6 +8,41 @@ use std::backtrace::Backtrace;
 use ash::vk;
 use log::{debug, Level};
 
+mod adopted_memory;
+pub use adopted_memory::{AdoptMemoryDesc, MemoryOwnership};
+
+mod bar;
+pub use bar::BarHeap;
+
+mod capabilities;
+pub use capabilities::DeviceCapabilities;
+
+mod budget;
+pub use budget::{BudgetGroup, HeapBudget, MemoryBudgetMode};
+
+mod non_coherent;
+
+mod external_memory;
+pub use external_memory::ImportMemoryDesc;
+
+mod limits;
+
+mod mapping;
+#[cfg(feature = "std")]
+pub use mapping::MappedAllocationCursor;
+
+mod memory_priority;
+
+mod memory_properties;
+use memory_properties::MemoryTypeFilter;
+pub use memory_properties::{MemoryLocationPreferences, MemoryPropertyPreferences};
+
+mod resources;
+pub use resources::ResourceAllocationDesc;
+
+mod sparse;
+pub use sparse::{SparseAllocation, SparseAllocationCreateDesc, SparseImageTile};
+
 #[cfg(feature = "visualizer")]
 mod visualizer;
 #[cfg(feature = "visualizer")]
@@ -15,8 +50,8 @@ pub use visualizer::AllocatorVisualizer;
 
 use crate::{
     allocator::{
-        AllocationType, AllocatorReport, DedicatedBlockAllocator, FreeListAllocator,
-        MemoryBlockReport, SubAllocator,
+        self, align_up, AllocationType, AllocatorReport, FaultInjector, MemoryDevice, SendSyncPtr,
+        SubAllocationDesc,
     },
     AllocationError, AllocationSizes, AllocatorDebugSettings, MemoryLocation, Result,
 };
@@ -31,6 +66,18 @@ pub enum AllocationScheme {
     DedicatedImage(vk::Image),
     /// The memory for this resource will be allocated and managed by gpu-allocator.
     GpuAllocatorManaged,
+    /// Query `VkMemoryDedicatedRequirements` for the given buffer, and behave like
+    /// [`AllocationScheme::DedicatedBuffer`] if the driver prefers or requires a dedicated
+    /// allocation, or like [`AllocationScheme::GpuAllocatorManaged`] otherwise.  Preferred
+    /// dedicated allocations are skipped once `maxMemoryAllocationCount` is nearly reached.
+    /// Requires Vulkan 1.1.
+    AutoBuffer(vk::Buffer),
+    /// Query `VkMemoryDedicatedRequirements` for the given image, and behave like
+    /// [`AllocationScheme::DedicatedImage`] if the driver prefers or requires a dedicated
+    /// allocation, or like [`AllocationScheme::GpuAllocatorManaged`] otherwise.  Preferred
+    /// dedicated allocations are skipped once `maxMemoryAllocationCount` is nearly reached.
+    /// Requires Vulkan 1.1.
+    AutoImage(vk::Image),
 }
 
 #[derive(Clone, Debug)]
@@ -45,28 +92,147 @@ pub struct AllocationCreateDesc<'a> {
     pub linear: bool,
     /// Determines how this allocation should be managed.
     pub allocation_scheme: AllocationScheme,
+    /// Budget group created with [`Allocator::create_budget_group()`] that this allocation counts
+    /// towards, if any.
+    pub budget_group: Option<BudgetGroup>,
+    /// Overrides [`AllocatorCreateDesc::memory_location_preferences`] for this allocation.
+    pub memory_properties: Option<&'a MemoryPropertyPreferences>,
+    /// Keep the memory of this allocation mapped until it is freed, so that
+    /// [`Allocation::mapped_ptr()`] is available.  Only has an effect with
+    /// [`AllocatorCreateDesc::map_on_demand`], host-visible memory is always mapped otherwise.
+    /// Implied for [`MemoryLocation::GpuOnly`] allocations on unified memory devices, see
+    /// [`Allocator::is_unified_memory()`].
+    pub persistently_mapped: bool,
+    /// Handle types to make the memory of this allocation exportable with, for example through
+    /// [`Allocation::export_fd()`].  Exportable allocations always get their own `VkDeviceMemory`;
+    /// use a dedicated [`AllocationScheme`] for resources that can only be exported with
+    /// dedicated memory.
+    pub export_handle_types: vk::ExternalMemoryHandleTypeFlags,
+    /// Priority of the memory of this allocation between `0.0` and `1.0`, which tells the driver
+    /// what to evict from device-local memory first under memory pressure.  [`None`] uses the
+    /// default priority of `0.5`.  Only has an effect with
+    /// [`AllocatorCreateDesc::memory_priority`]; allocations with different priorities never share
+    /// memory blocks then.
+    pub priority: Option<f32>,
+    /// Allocate memory for a protected resource from a
+    /// [`vk::MemoryPropertyFlags::PROTECTED`] memory type.  Protected memory is never shared
+    /// with unprotected allocations and cannot be mapped, so [`MemoryLocation::CpuToGpu`],
+    /// [`MemoryLocation::GpuToCpu`] and [`Self::persistently_mapped`] are rejected.
+    pub protected: bool,
+    /// Physical devices of the device group to allocate memory on, as a
+    /// `VkMemoryAllocateFlagsInfo::deviceMask`.  `0` allocates on all of them, like a mask of all
+    /// devices.  Allocations with different device masks never share memory blocks.
+    pub device_mask: u32,
+    /// Whether to allocate memory with [`vk::MemoryAllocateFlags::DEVICE_ADDRESS`], for buffers
+    /// with [`vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS`].  [`None`] follows
+    /// [`AllocatorCreateDesc::buffer_device_address`], and `Some(true)` needs it.  Allocations with
+    /// and without it never share memory blocks.
+    pub buffer_device_address: Option<bool>,
 }
 
-/// Wrapper type to only mark a raw pointer [`Send`] + [`Sync`] without having to
-/// mark the entire [`Allocation`] as such, instead relying on the compiler to
-/// auto-implement this or fail if fields are added that violate this constraint
-#[derive(Clone, Copy, Debug)]
-pub(crate) struct SendSyncPtr(core::ptr::NonNull<core::ffi::c_void>);
-// Sending is fine because mapped_ptr does not change based on the thread we are in
-unsafe impl Send for SendSyncPtr {}
-// Sync is also okay because Sending &Allocation is safe: a mutable reference
-// to the data in mapped_ptr is never exposed while `self` is immutably borrowed.
-// In order to break safety guarantees, the user needs to `unsafe`ly dereference
-// `mapped_ptr` themselves.
-unsafe impl Sync for SendSyncPtr {}
+impl Default for AllocationCreateDesc<'_> {
+    /// An unnamed, empty [`MemoryLocation::Unknown`] allocation, with every option left at its
+    /// default.  Meant to be completed with `..Default::default()`.
+    fn default() -> Self {
+        Self {
+            name: "",
+            requirements: vk::MemoryRequirements::default(),
+            location: MemoryLocation::Unknown,
+            linear: false,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            budget_group: None,
+            memory_properties: None,
+            persistently_mapped: false,
+            export_handle_types: vk::ExternalMemoryHandleTypeFlags::empty(),
+            priority: None,
+            protected: false,
+            device_mask: 0,
+            buffer_device_address: None,
+        }
+    }
+}
 
+/// Describes how to create an [`Allocator`].  Use [`AllocatorCreateDesc::new()`] to leave the
+/// fields you don't need at their defaults.
 pub struct AllocatorCreateDesc {
     pub instance: ash::Instance,
     pub device: ash::Device,
     pub physical_device: vk::PhysicalDevice,
     pub debug_settings: AllocatorDebugSettings,
+    /// Whether the `bufferDeviceAddress` feature is enabled on `device`.  Allocations then get
+    /// [`vk::MemoryAllocateFlags::DEVICE_ADDRESS`] unless
+    /// [`AllocationCreateDesc::buffer_device_address`] opts out.  With
+    /// [`Self::device_capabilities`], [`Allocator::new()`] fails unless
+    /// [`DeviceCapabilities::buffer_device_address`] is set as well.
     pub buffer_device_address: bool,
     pub allocation_sizes: AllocationSizes,
+    /// Whether to use `VK_EXT_memory_budget` to report and optionally enforce per-heap budgets.
+    pub memory_budget: MemoryBudgetMode,
+    /// Optional size limits in bytes, indexed by memory heap index, that the allocator treats as
+    /// the real capacity of these heaps.  Missing entries and [`None`] leave a heap unlimited.
+    ///
+    /// Useful to reproduce the behavior of devices with less memory.
+    pub heap_size_limits: Vec<Option<u64>>,
+    /// Memory property flags to look for when picking a memory type for every [`MemoryLocation`],
+    /// including the fallbacks to try when allocating from that memory type fails.
+    pub memory_location_preferences: MemoryLocationPreferences,
+    /// Maximum number of bytes to allocate from the [`BarHeap`] when it is not resizable, to keep
+    /// some of it available to the driver and other applications.  [`None`] leaves it uncapped.
+    pub small_bar_usage_limit: Option<u64>,
+    /// Leave host-visible memory blocks unmapped until [`Allocator::map()`] is called, instead of
+    /// mapping them for their whole lifetime.  Saves address space on 32-bit platforms and some
+    /// mobile drivers.
+    pub map_on_demand: bool,
+    /// Whether `VK_EXT_memory_priority` is enabled, to pass [`AllocationCreateDesc::priority`] on
+    /// to the driver.
+    pub memory_priority: bool,
+    /// Whether `VK_EXT_pageable_device_local_memory` is enabled, to change the priority of existing
+    /// allocations with [`Allocator::set_memory_priority()`].
+    pub pageable_device_local_memory: bool,
+    /// Number of physical devices in the device group the logical device was created with, see
+    /// `VK_KHR_device_group`, at most [`vk::MAX_DEVICE_GROUP_SIZE`].  `0` is treated as `1`.
+    pub physical_device_count: u32,
+    /// The API version, extensions and features `device` was created with.  The allocator then
+    /// only uses what is enabled, and [`Allocator::new()`] fails if this configuration needs a
+    /// missing capability.  [`None`] assumes at least Vulkan 1.1 and trusts the other fields.
+    pub device_capabilities: Option<DeviceCapabilities>,
+}
+
+impl AllocatorCreateDesc {
+    /// Describes an allocator for `device` with every option left at its default, to be completed
+    /// with struct update syntax:
+    ///
+    /// ```no_run
+    /// # use gpu_allocator::vulkan::*;
+    /// # let (instance, device, physical_device) = todo!();
+    /// let desc = AllocatorCreateDesc {
+    ///     buffer_device_address: true,
+    ///     ..AllocatorCreateDesc::new(instance, device, physical_device)
+    /// };
+    /// ```
+    pub fn new(
+        instance: ash::Instance,
+        device: ash::Device,
+        physical_device: vk::PhysicalDevice,
+    ) -> Self {
+        Self {
+            instance,
+            device,
+            physical_device,
+            debug_settings: AllocatorDebugSettings::default(),
+            buffer_device_address: false,
+            allocation_sizes: AllocationSizes::default(),
+            memory_budget: MemoryBudgetMode::default(),
+            heap_size_limits: Vec::new(),
+            memory_location_preferences: MemoryLocationPreferences::default(),
+            small_bar_usage_limit: None,
+            map_on_demand: false,
+            memory_priority: false,
+            pageable_device_local_memory: false,
+            physical_device_count: 1,
+            device_capabilities: None,
+        }
+    }
 }
 
 /// A piece of allocated memory.
@@ -162,9 +328,19 @@ pub struct Allocation {
     memory_type_index: usize,
     device_memory: vk::DeviceMemory,
     mapped_ptr: Option<SendSyncPtr>,
+    /// Whether this allocation keeps its memory block mapped on demand until it is freed.
+    holds_mapping: bool,
+    /// Number of mappings of this allocation through [`Allocator::map()`] that were not released
+    /// yet.
+    map_count: u32,
     dedicated_allocation: bool,
     memory_properties: vk::MemoryPropertyFlags,
     name: Option<Box<str>>,
+    budget_group: Option<BudgetGroup>,
+    used_fallback: bool,
+    export_handle_types: vk::ExternalMemoryHandleTypeFlags,
+    imported: bool,
+    device_address: Option<vk::DeviceAddress>,
 }
 
 impl Allocation {
@@ -208,6 +384,25 @@ impl Allocation {
         self.chunk_id
     }
 
+    /// Returns the index of the Vulkan memory type this allocation was made in.
+    pub fn memory_type_index(&self) -> usize {
+        self.memory_type_index
+    }
+
+    /// Returns the device address of the buffer this allocation was bound to by
+    /// [`Allocator::create_buffer()`], if it was created with
+    /// [`vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS`].
+    pub fn device_address(&self) -> Option<vk::DeviceAddress> {
+        self.device_address
+    }
+
+    /// Returns [`true`] if this allocation was not made in the highest ranked memory type, because
+    /// that one ran out of memory. The allocation then landed in another memory type with the
+    /// required flags or in one of the [`MemoryPropertyPreferences::fallbacks`].
+    pub fn used_fallback(&self) -> bool {
+        self.used_fallback
+    }
+
     ///Returns the [`vk::MemoryPropertyFlags`] of this allocation.
     pub fn memory_properties(&self) -> vk::MemoryPropertyFlags {
         self.memory_properties
@@ -282,9 +477,16 @@ impl Default for Allocation {
             memory_type_index: !0,
             device_memory: vk::DeviceMemory::null(),
             mapped_ptr: None,
+            holds_mapping: false,
+            map_count: 0,
             memory_properties: vk::MemoryPropertyFlags::empty(),
             name: None,
             dedicated_allocation: false,
+            budget_group: None,
+            used_fallback: false,
+            export_handle_types: vk::ExternalMemoryHandleTypeFlags::empty(),
+            imported: false,
+            device_address: None,
         }
     }
 }
@@ -340,359 +542,222 @@ unsafe impl presser::Slab for Allocation {
     }
 }
 
+/// Vulkan-specific description of a memory type.
 #[derive(Debug)]
-pub(crate) struct MemoryBlock {
-    pub(crate) device_memory: vk::DeviceMemory,
-    pub(crate) size: u64,
-    pub(crate) mapped_ptr: Option<SendSyncPtr>,
-    pub(crate) sub_allocator: Box<dyn SubAllocator>,
-    #[cfg(feature = "visualizer")]
-    pub(crate) dedicated_allocation: bool,
+pub(crate) struct MemoryTypeInfo {
+    pub(crate) memory_properties: vk::MemoryPropertyFlags,
+    pub(crate) heap_index: usize,
+    pub(crate) mappable: bool,
+    /// Whether the heap of this memory type is replicated on every physical device of the group.
+    pub(crate) multi_instance: bool,
+    /// Number of physical devices in the device group.
+    pub(crate) physical_device_count: u32,
 }
 
-impl MemoryBlock {
-    fn new(
-        device: &ash::Device,
-        size: u64,
-        mem_type_index: usize,
-        mapped: bool,
-        buffer_device_address: bool,
-        allocation_scheme: AllocationScheme,
-        requires_personal_block: bool,
-    ) -> Result<Self> {
-        let device_memory = {
-            let alloc_info = vk::MemoryAllocateInfo::default()
-                .allocation_size(size)
-                .memory_type_index(mem_type_index as u32);
-
-            let allocation_flags = vk::MemoryAllocateFlags::DEVICE_ADDRESS;
-            let mut flags_info = vk::MemoryAllocateFlagsInfo::default().flags(allocation_flags);
-            // TODO(manon): Test this based on if the device has this feature enabled or not
-            let alloc_info = if buffer_device_address {
-                alloc_info.push_next(&mut flags_info)
-            } else {
-                alloc_info
-            };
-
-            // Flag the memory as dedicated if required.
-            let mut dedicated_memory_info = vk::MemoryDedicatedAllocateInfo::default();
-            let alloc_info = match allocation_scheme {
-                AllocationScheme::DedicatedBuffer(buffer) => {
-                    dedicated_memory_info = dedicated_memory_info.buffer(buffer);
-                    alloc_info.push_next(&mut dedicated_memory_info)
-                }
-                AllocationScheme::DedicatedImage(image) => {
-                    dedicated_memory_info = dedicated_memory_info.image(image);
-                    alloc_info.push_next(&mut dedicated_memory_info)
-                }
-                AllocationScheme::GpuAllocatorManaged => alloc_info,
-            };
-
-            unsafe { device.allocate_memory(&alloc_info, None) }.map_err(|e| match e {
-                vk::Result::ERROR_OUT_OF_DEVICE_MEMORY => AllocationError::OutOfMemory,
-                e => AllocationError::Internal(format!(
-                    "Unexpected error in vkAllocateMemory: {e:?}"
-                )),
-            })?
-        };
-
-        let mapped_ptr = mapped
-            .then(|| {
-                unsafe {
-                    device.map_memory(
-                        device_memory,
-                        0,
-                        vk::WHOLE_SIZE,
-                        vk::MemoryMapFlags::empty(),
-                    )
-                }
-                .map_err(|e| {
-                    unsafe { device.free_memory(device_memory, None) };
-                    AllocationError::FailedToMap(e.to_string())
-                })
-                .and_then(|p| {
-                    core::ptr::NonNull::new(p).map(SendSyncPtr).ok_or_else(|| {
-                        AllocationError::FailedToMap("Returned mapped pointer is null".to_owned())
-                    })
-                })
-            })
-            .transpose()?;
-
-        let sub_allocator: Box<dyn SubAllocator> = if allocation_scheme
-            != AllocationScheme::GpuAllocatorManaged
-            || requires_personal_block
-        {
-            Box::new(DedicatedBlockAllocator::new(size))
-        } else {
-            Box::new(FreeListAllocator::new(size))
-        };
-
-        Ok(Self {
-            device_memory,
-            size,
-            mapped_ptr,
-            sub_allocator,
-            #[cfg(feature = "visualizer")]
-            dedicated_allocation: allocation_scheme != AllocationScheme::GpuAllocatorManaged,
-        })
-    }
-
-    fn destroy(self, device: &ash::Device) {
-        if self.mapped_ptr.is_some() {
-            unsafe { device.unmap_memory(self.device_memory) };
-        }
-
-        unsafe { device.free_memory(self.device_memory, None) };
+/// Returns the device mask to store in [`BlockParams`] for a requested `device_mask`.  A mask of all
+/// `physical_device_count` devices maps to `0`, so that allocations asking for all devices
+/// explicitly share memory blocks with those that leave the mask at `0`.
+fn normalized_device_mask(device_mask: u32, physical_device_count: u32) -> u32 {
+    let all_devices = u32::MAX
+        .checked_shr(u32::BITS - physical_device_count)
+        .unwrap_or(0);
+    if device_mask == all_devices {
+        0
+    } else {
+        device_mask
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct MemoryType {
-    pub(crate) memory_blocks: Vec<Option<MemoryBlock>>,
-    pub(crate) memory_properties: vk::MemoryPropertyFlags,
-    pub(crate) memory_type_index: usize,
-    pub(crate) heap_index: usize,
-    pub(crate) mappable: bool,
-    pub(crate) active_general_blocks: usize,
+/// Parameters of a single `VkDeviceMemory`, general blocks are only shared between allocations
+/// with equal parameters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct BlockParams {
+    pub(crate) allocation_scheme: AllocationScheme,
+    pub(crate) export_handle_types: vk::ExternalMemoryHandleTypeFlags,
+    /// Only set when `VK_EXT_memory_priority` is enabled, and never to the default priority of
+    /// `0.5`, see [`memory_priority::block_priority()`].
+    pub(crate) priority: Option<f32>,
+    /// `0` for all physical devices of the device group, see [`normalized_device_mask()`].
+    pub(crate) device_mask: u32,
     pub(crate) buffer_device_address: bool,
 }
 
-impl MemoryType {
-    fn allocate(
-        &mut self,
-        device: &ash::Device,
-        desc: &AllocationCreateDesc<'_>,
-        granularity: u64,
-        #[cfg(feature = "std")] backtrace: Arc<Backtrace>,
-        allocation_sizes: &AllocationSizes,
-    ) -> Result<Allocation> {
-        let allocation_type = if desc.linear {
-            AllocationType::Linear
-        } else {
-            AllocationType::NonLinear
-        };
-
-        let is_host = self
-            .memory_properties
-            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
-
-        let memblock_size = allocation_sizes.get_memblock_size(is_host, self.active_general_blocks);
-
-        let size = desc.requirements.size;
-        let alignment = desc.requirements.alignment;
-
-        let dedicated_allocation = desc.allocation_scheme != AllocationScheme::GpuAllocatorManaged;
-        let requires_personal_block = size > memblock_size;
-
-        // Create a dedicated block for large memory allocations or allocations that require dedicated memory allocations.
-        if dedicated_allocation || requires_personal_block {
-            let mem_block = MemoryBlock::new(
-                device,
-                size,
-                self.memory_type_index,
-                self.mappable,
-                self.buffer_device_address,
-                desc.allocation_scheme,
-                requires_personal_block,
-            )?;
-
-            let mut block_index = None;
-            for (i, block) in self.memory_blocks.iter().enumerate() {
-                if block.is_none() {
-                    block_index = Some(i);
-                    break;
-                }
-            }
+pub(crate) type MemoryType = allocator::MemoryType<ash::Device>;
 
-            let block_index = match block_index {
-                Some(i) => {
-                    self.memory_blocks[i].replace(mem_block);
-                    i
-                }
-                None => {
-                    self.memory_blocks.push(Some(mem_block));
-                    self.memory_blocks.len() - 1
-                }
-            };
-
-            let mem_block = self.memory_blocks[block_index]
-                .as_mut()
-                .ok_or_else(|| AllocationError::Internal("Memory block must be Some".into()))?;
+/// Allocates a `VkDeviceMemory` for a block with `params`, importing memory through `import_info`
+/// if given.
+fn allocate_device_memory(
+    device: &ash::Device,
+    memory_type_index: usize,
+    size: u64,
+    params: BlockParams,
+    import_info: Option<&mut dyn vk::ExtendsMemoryAllocateInfo>,
+) -> Result<vk::DeviceMemory> {
+    let alloc_info = vk::MemoryAllocateInfo::default()
+        .allocation_size(size)
+        .memory_type_index(memory_type_index as u32);
+
+    let mut allocation_flags = vk::MemoryAllocateFlags::empty();
+    if params.buffer_device_address {
+        allocation_flags |= vk::MemoryAllocateFlags::DEVICE_ADDRESS;
+    }
+    if params.device_mask != 0 {
+        allocation_flags |= vk::MemoryAllocateFlags::DEVICE_MASK;
+    }
+    let mut flags_info = vk::MemoryAllocateFlagsInfo::default()
+        .flags(allocation_flags)
+        .device_mask(params.device_mask);
+    let alloc_info = if allocation_flags.is_empty() {
+        alloc_info
+    } else {
+        alloc_info.push_next(&mut flags_info)
+    };
+
+    // Flag the memory as dedicated if required.
+    let mut dedicated_memory_info = vk::MemoryDedicatedAllocateInfo::default();
+    let alloc_info = match params.allocation_scheme {
+        AllocationScheme::DedicatedBuffer(buffer) => {
+            dedicated_memory_info = dedicated_memory_info.buffer(buffer);
+            alloc_info.push_next(&mut dedicated_memory_info)
+        }
+        AllocationScheme::DedicatedImage(image) => {
+            dedicated_memory_info = dedicated_memory_info.image(image);
+            alloc_info.push_next(&mut dedicated_memory_info)
+        }
+        AllocationScheme::GpuAllocatorManaged
+        | AllocationScheme::AutoBuffer(_)
+        | AllocationScheme::AutoImage(_) => alloc_info,
+    };
+
+    // Make the memory exportable if requested.
+    let mut export_memory_info =
+        vk::ExportMemoryAllocateInfo::default().handle_types(params.export_handle_types);
+    let alloc_info = if params.export_handle_types.is_empty() {
+        alloc_info
+    } else {
+        alloc_info.push_next(&mut export_memory_info)
+    };
+
+    // Tell the driver what to evict first, if requested.
+    let mut priority_info =
+        vk::MemoryPriorityAllocateInfoEXT::default().priority(params.priority.unwrap_or(0.5));
+    let alloc_info = if params.priority.is_some() {
+        alloc_info.push_next(&mut priority_info)
+    } else {
+        alloc_info
+    };
+
+    // Import existing memory instead of allocating new memory, if requested.
+    let alloc_info =
+        import_info.map_or(alloc_info, |import_info| alloc_info.push_next(import_info));
+
+    unsafe { device.allocate_memory(&alloc_info, None) }.map_err(|e| match e {
+        vk::Result::ERROR_OUT_OF_DEVICE_MEMORY => AllocationError::OutOfMemory,
+        e => AllocationError::Internal(format!("Unexpected error in vkAllocateMemory: {e:?}")),
+    })
+}
 
-            let (offset, chunk_id) = mem_block.sub_allocator.allocate(
-                size,
-                alignment,
-                allocation_type,
-                granularity,
-                desc.name,
-                #[cfg(feature = "std")]
-                backtrace,
-            )?;
+impl MemoryDevice for ash::Device {
+    type Memory = vk::DeviceMemory;
+    type MemoryTypeInfo = MemoryTypeInfo;
+    type BlockParams = BlockParams;
 
-            return Ok(Allocation {
-                chunk_id: Some(chunk_id),
-                offset,
-                size,
-                memory_block_index: block_index,
-                memory_type_index: self.memory_type_index,
-                device_memory: mem_block.device_memory,
-                mapped_ptr: mem_block.mapped_ptr,
-                memory_properties: self.memory_properties,
-                name: Some(desc.name.into()),
-                dedicated_allocation,
-            });
-        }
-
-        let mut empty_block_index = None;
-        for (mem_block_i, mem_block) in self.memory_blocks.iter_mut().enumerate().rev() {
-            if let Some(mem_block) = mem_block {
-                let allocation = mem_block.sub_allocator.allocate(
-                    size,
-                    alignment,
-                    allocation_type,
-                    granularity,
-                    desc.name,
-                    #[cfg(feature = "std")]
-                    backtrace.clone(),
-                );
+    fn create_block(
+        &self,
+        _memory_type: &MemoryTypeInfo,
+        memory_type_index: usize,
+        size: u64,
+        params: BlockParams,
+    ) -> Result<vk::DeviceMemory> {
+        allocate_device_memory(self, memory_type_index, size, params, None)
+    }
 
-                match allocation {
-                    Ok((offset, chunk_id)) => {
-                        let mapped_ptr = if let Some(SendSyncPtr(mapped_ptr)) = mem_block.mapped_ptr
-                        {
-                            let offset_ptr = unsafe { mapped_ptr.as_ptr().add(offset as usize) };
-                            core::ptr::NonNull::new(offset_ptr).map(SendSyncPtr)
-                        } else {
-                            None
-                        };
-                        return Ok(Allocation {
-                            chunk_id: Some(chunk_id),
-                            offset,
-                            size,
-                            memory_block_index: mem_block_i,
-                            memory_type_index: self.memory_type_index,
-                            device_memory: mem_block.device_memory,
-                            memory_properties: self.memory_properties,
-                            mapped_ptr,
-                            dedicated_allocation: false,
-                            name: Some(desc.name.into()),
-                        });
-                    }
-                    Err(err) => match err {
-                        AllocationError::OutOfMemory => {} // Block is full, continue search.
-                        _ => return Err(err),              // Unhandled error, return.
-                    },
-                }
-            } else if empty_block_index.is_none() {
-                empty_block_index = Some(mem_block_i);
-            }
+    fn map_block(
+        &self,
+        memory_type: &MemoryTypeInfo,
+        memory: &vk::DeviceMemory,
+    ) -> Result<Option<core::ptr::NonNull<core::ffi::c_void>>> {
+        if !memory_type.mappable {
+            return Ok(None);
         }
 
-        let new_memory_block = MemoryBlock::new(
-            device,
-            memblock_size,
-            self.memory_type_index,
-            self.mappable,
-            self.buffer_device_address,
-            desc.allocation_scheme,
-            false,
-        )?;
-
-        let new_block_index = if let Some(block_index) = empty_block_index {
-            self.memory_blocks[block_index] = Some(new_memory_block);
-            block_index
-        } else {
-            self.memory_blocks.push(Some(new_memory_block));
-            self.memory_blocks.len() - 1
-        };
+        let mapped_ptr =
+            unsafe { self.map_memory(*memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()) }
+                .map_err(|e| AllocationError::FailedToMap(e.to_string()))?;
 
-        self.active_general_blocks += 1;
+        core::ptr::NonNull::new(mapped_ptr)
+            .map(Some)
+            .ok_or_else(|| {
+                AllocationError::FailedToMap("Returned mapped pointer is null".to_owned())
+            })
+    }
 
-        let mem_block = self.memory_blocks[new_block_index]
-            .as_mut()
-            .ok_or_else(|| AllocationError::Internal("Memory block must be Some".into()))?;
-        let allocation = mem_block.sub_allocator.allocate(
-            size,
-            alignment,
-            allocation_type,
-            granularity,
-            desc.name,
-            #[cfg(feature = "std")]
-            backtrace,
-        );
-        let (offset, chunk_id) = match allocation {
-            Ok(value) => value,
-            Err(err) => match err {
-                AllocationError::OutOfMemory => {
-                    return Err(AllocationError::Internal(
-                        "Allocation that must succeed failed. This is a bug in the allocator."
-                            .into(),
-                    ))
-                }
-                _ => return Err(err),
-            },
-        };
+    fn destroy_block(&self, _memory_type: &MemoryTypeInfo, memory: vk::DeviceMemory, mapped: bool) {
+        if mapped {
+            unsafe { self.unmap_memory(memory) };
+        }
 
-        let mapped_ptr = if let Some(SendSyncPtr(mapped_ptr)) = mem_block.mapped_ptr {
-            let offset_ptr = unsafe { mapped_ptr.as_ptr().add(offset as usize) };
-            core::ptr::NonNull::new(offset_ptr).map(SendSyncPtr)
-        } else {
-            None
-        };
+        unsafe { self.free_memory(memory, None) };
+    }
 
-        Ok(Allocation {
-            chunk_id: Some(chunk_id),
-            offset,
-            size,
-            memory_block_index: new_block_index,
-            memory_type_index: self.memory_type_index,
-            device_memory: mem_block.device_memory,
-            mapped_ptr,
-            memory_properties: self.memory_properties,
-            name: Some(desc.name.into()),
-            dedicated_allocation: false,
-        })
+    fn unmap_block(&self, _memory_type: &MemoryTypeInfo, memory: &vk::DeviceMemory) {
+        unsafe { self.unmap_memory(*memory) };
     }
 
-    #[allow(clippy::needless_pass_by_value)]
-    fn free(&mut self, allocation: Allocation, device: &ash::Device) -> Result<()> {
-        let block_idx = allocation.memory_block_index;
-
-        let mem_block = self.memory_blocks[block_idx]
-            .as_mut()
-            .ok_or_else(|| AllocationError::Internal("Memory block must be Some.".into()))?;
-
-        mem_block.sub_allocator.free(allocation.chunk_id)?;
-
-        // We only want to destroy this now-empty block if it is either a dedicated/personal
-        // allocation, or a block supporting sub-allocations that is not the last one (ensuring
-        // there's always at least one block/allocator readily available).
-        let is_dedicated_or_not_last_general_block =
-            !mem_block.sub_allocator.supports_general_allocations()
-                || self.active_general_blocks > 1;
-        if mem_block.sub_allocator.is_empty() && is_dedicated_or_not_last_general_block {
-            let block = self.memory_blocks[block_idx]
-                .take()
-                .ok_or_else(|| AllocationError::Internal("Memory block must be Some.".into()))?;
-
-            if block.sub_allocator.supports_general_allocations() {
-                self.active_general_blocks -= 1;
-            }
+    fn is_lazily_allocated(memory_type: &MemoryTypeInfo) -> bool {
+        memory_type
+            .memory_properties
+            .contains(vk::MemoryPropertyFlags::LAZILY_ALLOCATED)
+    }
 
-            block.destroy(device);
+    fn block_instances(memory_type: &MemoryTypeInfo, params: &BlockParams) -> u32 {
+        if !memory_type.multi_instance {
+            1
+        } else if params.device_mask != 0 {
+            params.device_mask.count_ones()
+        } else {
+            memory_type.physical_device_count
         }
+    }
 
-        Ok(())
+    fn is_protected(memory_type: &MemoryTypeInfo) -> bool {
+        memory_type
+            .memory_properties
+            .contains(vk::MemoryPropertyFlags::PROTECTED)
     }
 }
 
 pub struct Allocator {
     pub(crate) memory_types: Vec<MemoryType>,
     pub(crate) memory_heaps: Vec<vk::MemoryHeap>,
+    instance: ash::Instance,
     device: ash::Device,
+    physical_device: vk::PhysicalDevice,
     pub(crate) buffer_image_granularity: u64,
+    non_coherent_atom_size: u64,
     pub(crate) debug_settings: AllocatorDebugSettings,
     allocation_sizes: AllocationSizes,
+    fault_injector: FaultInjector,
+    memory_budget: MemoryBudgetMode,
+    heap_size_limits: Vec<Option<u64>>,
+    /// Only kept up to date with [`MemoryBudgetMode::Enforce`].
+    budget_snapshot: Option<budget::BudgetSnapshot>,
+    budget_groups: Vec<budget::BudgetGroupState>,
+    /// Identity of this allocator, stored in its [`BudgetGroup`]s.
+    allocator_id: usize,
+    memory_location_preferences: MemoryLocationPreferences,
+    fallback_allocations: u64,
+    bar_heap: Option<BarHeap>,
+    unified_memory: bool,
+    memory_priority: bool,
+    pageable_device_local_memory: Option<ash::ext::pageable_device_local_memory::Device>,
+    physical_device_count: u32,
+    /// Loaded if [`AllocatorCreateDesc::buffer_device_address`] is set.
+    buffer_device_address: Option<capabilities::BufferDeviceAddress>,
+    device_capabilities: Option<DeviceCapabilities>,
+    maintenance4: Option<capabilities::Maintenance4>,
+    max_memory_allocation_count: u32,
+    max_memory_allocation_size: u64,
 }
 
 impl fmt::Debug for Allocator {
@@ -709,17 +774,62 @@ impl Allocator {
             ));
         }
 
+        if desc.physical_device_count > vk::MAX_DEVICE_GROUP_SIZE as u32 {
+            return Err(AllocationError::InvalidAllocatorCreateDesc(format!(
+                "AllocatorCreateDesc field `physical_device_count` is {}, but device groups have at most {} physical devices.",
+                desc.physical_device_count,
+                vk::MAX_DEVICE_GROUP_SIZE
+            )));
+        }
+
+        if let Some(device_capabilities) = &desc.device_capabilities {
+            device_capabilities.validate(desc)?;
+        }
+
         let mem_props = unsafe {
             desc.instance
                 .get_physical_device_memory_properties(desc.physical_device)
         };
 
         let memory_types = &mem_props.memory_types_as_slice();
-        let memory_heaps = mem_props.memory_heaps_as_slice().to_vec();
+        let mut memory_heaps = mem_props.memory_heaps_as_slice().to_vec();
+
+        if desc.heap_size_limits.len() > memory_heaps.len() {
+            return Err(AllocationError::InvalidAllocatorCreateDesc(format!(
+                "AllocatorCreateDesc field `heap_size_limits` has {} entries, but the device only has {} memory heaps.",
+                desc.heap_size_limits.len(),
+                memory_heaps.len()
+            )));
+        }
+
+        let physical_device_properties = unsafe {
+            desc.instance
+                .get_physical_device_properties(desc.physical_device)
+        };
+
+        let bar_heap = bar::find_bar_heap(memory_types, &memory_heaps);
+        let unified_memory =
+            bar::is_unified_memory(physical_device_properties.device_type, memory_types);
+
+        let mut heap_size_limits = desc.heap_size_limits.clone();
+        heap_size_limits.resize(memory_heaps.len(), None);
+        if let (Some(bar_heap), Some(usage_limit)) = (bar_heap, desc.small_bar_usage_limit) {
+            if !bar_heap.resizable {
+                let limit = &mut heap_size_limits[bar_heap.heap_index];
+                *limit = Some(limit.map_or(usage_limit, |limit| limit.min(usage_limit)));
+            }
+        }
+        for (heap, limit) in memory_heaps.iter_mut().zip(&heap_size_limits) {
+            if let Some(limit) = *limit {
+                heap.size = heap.size.min(limit);
+            }
+        }
 
         if desc.debug_settings.log_memory_information {
             debug!("memory type count: {}", mem_props.memory_type_count);
             debug!("memory heap count: {}", mem_props.memory_heap_count);
+            debug!("BAR heap: {bar_heap:?}");
+            debug!("unified memory: {unified_memory}");
 
             for (i, mem_type) in memory_types.iter().enumerate() {
                 let flags = mem_type.property_flags;
@@ -732,48 +842,118 @@ impl Allocator {
             }
             for (i, heap) in memory_heaps.iter().enumerate() {
                 debug!(
-                    "heap[{}] flags: 0x{:x}, size: {} MiB",
+                    "heap[{}] flags: 0x{:x}, size: {} MiB{}",
                     i,
                     heap.flags.as_raw(),
-                    heap.size / (1024 * 1024)
+                    heap.size / (1024 * 1024),
+                    if heap_size_limits[i].is_some() {
+                        " (limited)"
+                    } else {
+                        ""
+                    }
                 );
             }
         }
 
+        let physical_device_count = desc.physical_device_count.max(1);
         let memory_types = memory_types
             .iter()
             .enumerate()
-            .map(|(i, mem_type)| MemoryType {
-                memory_blocks: Vec::default(),
-                memory_properties: mem_type.property_flags,
-                memory_type_index: i,
-                heap_index: mem_type.heap_index as usize,
-                mappable: mem_type
+            .map(|(i, mem_type)| {
+                let mappable = mem_type
                     .property_flags
-                    .contains(vk::MemoryPropertyFlags::HOST_VISIBLE),
-                active_general_blocks: 0,
-                buffer_device_address: desc.buffer_device_address,
+                    .contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
+
+                let mut memory_type = MemoryType::new(
+                    i,
+                    mappable,
+                    MemoryTypeInfo {
+                        memory_properties: mem_type.property_flags,
+                        heap_index: mem_type.heap_index as usize,
+                        mappable,
+                        multi_instance: memory_heaps[mem_type.heap_index as usize]
+                            .flags
+                            .contains(vk::MemoryHeapFlags::MULTI_INSTANCE),
+                        physical_device_count,
+                    },
+                );
+                memory_type.map_on_demand = desc.map_on_demand;
+                memory_type
             })
             .collect::<Vec<_>>();
 
-        let physical_device_properties = unsafe {
-            desc.instance
-                .get_physical_device_properties(desc.physical_device)
-        };
-
         let granularity = physical_device_properties.limits.buffer_image_granularity;
 
+        // `VkPhysicalDeviceMaintenance3Properties` is part of Vulkan 1.1.
+        let max_memory_allocation_size = if desc
+            .device_capabilities
+            .as_ref()
+            .map_or(true, |capabilities| {
+                capabilities.api_version >= vk::API_VERSION_1_1
+            }) {
+            limits::max_memory_allocation_size(&desc.instance, desc.physical_device)
+        } else {
+            u64::MAX
+        };
+
         Ok(Self {
             memory_types,
             memory_heaps,
+            instance: desc.instance.clone(),
             device: desc.device.clone(),
+            physical_device: desc.physical_device,
             buffer_image_granularity: granularity,
+            non_coherent_atom_size: physical_device_properties.limits.non_coherent_atom_size,
             debug_settings: desc.debug_settings,
             allocation_sizes: desc.allocation_sizes,
+            fault_injector: FaultInjector::new(desc.debug_settings.fault_injection),
+            memory_budget: desc.memory_budget,
+            heap_size_limits,
+            budget_snapshot: None,
+            budget_groups: Vec::new(),
+            allocator_id: budget::next_allocator_id(),
+            memory_location_preferences: desc.memory_location_preferences.clone(),
+            fallback_allocations: 0,
+            bar_heap,
+            unified_memory,
+            memory_priority: desc.memory_priority,
+            pageable_device_local_memory: desc.pageable_device_local_memory.then(|| {
+                ash::ext::pageable_device_local_memory::Device::new(&desc.instance, &desc.device)
+            }),
+            physical_device_count,
+            buffer_device_address: desc.buffer_device_address.then(|| {
+                capabilities::BufferDeviceAddress::load(
+                    desc.device_capabilities.as_ref(),
+                    &desc.instance,
+                    &desc.device,
+                )
+            }),
+            device_capabilities: desc.device_capabilities.clone(),
+            maintenance4: capabilities::Maintenance4::load(
+                desc.device_capabilities.as_ref(),
+                &desc.instance,
+                &desc.device,
+            ),
+            max_memory_allocation_count: physical_device_properties
+                .limits
+                .max_memory_allocation_count,
+            max_memory_allocation_size,
         })
     }
 
     pub fn allocate(&mut self, desc: &AllocationCreateDesc<'_>) -> Result<Allocation> {
+        let resolved_desc;
+        let desc = match desc.allocation_scheme {
+            AllocationScheme::AutoBuffer(_) | AllocationScheme::AutoImage(_) => {
+                resolved_desc = AllocationCreateDesc {
+                    allocation_scheme: self.resolve_allocation_scheme(desc.allocation_scheme),
+                    ..desc.clone()
+                };
+                &resolved_desc
+            }
+            _ => desc,
+        };
+
         let size = desc.requirements.size;
         let alignment = desc.requirements.alignment;
 
@@ -796,90 +976,290 @@ impl Allocator {
             }
         }
 
-        if size == 0 || !alignment.is_power_of_two() {
+        if size == 0
+            || !alignment.is_power_of_two()
+            || desc
+                .priority
+                .is_some_and(|priority| !(0.0..=1.0).contains(&priority))
+            || desc
+                .device_mask
+                .checked_shr(self.physical_device_count)
+                .is_some_and(|mask| mask != 0)
+            || desc.buffer_device_address == Some(true) && self.buffer_device_address.is_none()
+            || !desc.export_handle_types.is_empty() && !self.supports_external_memory()
+            || desc.protected
+                && (desc.persistently_mapped
+                    || matches!(
+                        desc.location,
+                        MemoryLocation::CpuToGpu | MemoryLocation::GpuToCpu
+                    ))
+        {
             return Err(AllocationError::InvalidAllocationCreateDesc);
         }
 
-        let mem_loc_preferred_bits = match desc.location {
-            MemoryLocation::GpuOnly => vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            MemoryLocation::CpuToGpu => {
-                vk::MemoryPropertyFlags::HOST_VISIBLE
-                    | vk::MemoryPropertyFlags::HOST_COHERENT
-                    | vk::MemoryPropertyFlags::DEVICE_LOCAL
-            }
-            MemoryLocation::GpuToCpu => {
-                vk::MemoryPropertyFlags::HOST_VISIBLE
-                    | vk::MemoryPropertyFlags::HOST_COHERENT
-                    | vk::MemoryPropertyFlags::HOST_CACHED
+        self.check_allocation_size(size)?;
+
+        if let Some(budget_group) = desc.budget_group {
+            self.check_budget_group(budget_group, size)?;
+        }
+
+        let mut filter = self.memory_property_preferences(desc).filter();
+        if desc.memory_properties.is_none() {
+            // Guarantee a mapped pointer, so uploads can skip staging buffers.  Some resources
+            // such as optimally tiled images may still not support host-visible memory.
+            let host_visible = vk::MemoryPropertyFlags::HOST_VISIBLE;
+            if desc.location == MemoryLocation::GpuOnly
+                && self.unified_memory
+                && !desc.protected
+                && self
+                    .find_memorytype_index(
+                        desc.requirements.memory_type_bits,
+                        vk::MemoryPropertyFlags::DEVICE_LOCAL | host_visible,
+                        &filter,
+                    )
+                    .is_some()
+            {
+                filter.require(host_visible);
+                filter.preferred |= vk::MemoryPropertyFlags::HOST_COHERENT;
             }
-            MemoryLocation::Unknown => vk::MemoryPropertyFlags::empty(),
-        };
-        let mut memory_type_index_opt =
-            self.find_memorytype_index(&desc.requirements, mem_loc_preferred_bits);
-
-        if memory_type_index_opt.is_none() {
-            let mem_loc_required_bits = match desc.location {
-                MemoryLocation::GpuOnly => vk::MemoryPropertyFlags::DEVICE_LOCAL,
-                MemoryLocation::CpuToGpu | MemoryLocation::GpuToCpu => {
-                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
-                }
-                MemoryLocation::Unknown => vk::MemoryPropertyFlags::empty(),
+        }
+
+        // Protected resources need protected memory, see `find_memorytype_index()`.
+        if desc.protected {
+            filter.require(vk::MemoryPropertyFlags::PROTECTED);
+        }
+
+        let memory_type_index = self
+            .find_memorytype_index(desc.requirements.memory_type_bits, filter.required, &filter)
+            .ok_or(AllocationError::NoCompatibleMemoryTypeFound)?;
+
+        let mut tried_memory_types = 1u32 << memory_type_index;
+        let mut allocation = self.allocate_in_memory_type(
+            memory_type_index as usize,
+            desc,
+            #[cfg(feature = "std")]
+            backtrace.clone(),
+        );
+
+        // Try the remaining memory types with the required flags first, then every fallback. Only
+        // running out of memory is worth a retry, any other error fails the same way everywhere.
+        let mut fallback_index = None;
+        while matches!(allocation, Err(AllocationError::OutOfMemory)) {
+            let flags = match fallback_index {
+                None => filter.required,
+                Some(i) => match self.memory_property_preferences(desc).fallbacks.get(i) {
+                    Some(&flags) => flags | filter.fallback_required,
+                    None => break,
+                },
+            };
+
+            let Some(memory_type_index) = self.find_memorytype_index(
+                desc.requirements.memory_type_bits & !tried_memory_types,
+                flags,
+                &filter,
+            ) else {
+                fallback_index = Some(fallback_index.map_or(0, |i| i + 1));
+                continue;
             };
+            tried_memory_types |= 1 << memory_type_index;
+
+            debug!(
+                "Allocating `{}` retries in memory type {} with {:?}.",
+                desc.name, memory_type_index, flags
+            );
+            allocation = self
+                .allocate_in_memory_type(
+                    memory_type_index as usize,
+                    desc,
+                    #[cfg(feature = "std")]
+                    backtrace.clone(),
+                )
+                .map(|allocation| Allocation {
+                    used_fallback: true,
+                    ..allocation
+                });
+        }
 
-            memory_type_index_opt =
-                self.find_memorytype_index(&desc.requirements, mem_loc_required_bits);
+        if allocation.as_ref().is_ok_and(Allocation::used_fallback) {
+            self.fallback_allocations += 1;
         }
 
-        let memory_type_index = match memory_type_index_opt {
-            Some(x) => x as usize,
-            None => return Err(AllocationError::NoCompatibleMemoryTypeFound),
+        allocation
+    }
+
+    fn allocate_in_memory_type(
+        &mut self,
+        memory_type_index: usize,
+        desc: &AllocationCreateDesc<'_>,
+        #[cfg(feature = "std")] backtrace: Arc<Backtrace>,
+    ) -> Result<Allocation> {
+        let allocation_type = if desc.linear {
+            AllocationType::Linear
+        } else {
+            AllocationType::NonLinear
         };
 
+        // Transient attachments in lazily allocated memory always get their own memory block, and
+        // so do exportable allocations, whose handles would expose any other allocation in it.
+        let dedicated_allocation = desc.allocation_scheme != AllocationScheme::GpuAllocatorManaged
+            || !desc.export_handle_types.is_empty()
+            || self.memory_types[memory_type_index]
+                .info
+                .memory_properties
+                .contains(vk::MemoryPropertyFlags::LAZILY_ALLOCATED);
+
+        // Pad allocations in non-coherent memory to whole atoms, so that flushing or invalidating
+        // them never touches their neighbours.
+        let (size, alignment) = if non_coherent::is_non_coherent(
+            self.memory_types[memory_type_index].info.memory_properties,
+        ) {
+            let atom_size = self.non_coherent_atom_size;
+            (
+                align_up(desc.requirements.size, atom_size),
+                desc.requirements.alignment.max(atom_size),
+            )
+        } else {
+            (desc.requirements.size, desc.requirements.alignment)
+        };
+
+        let heap_index = self.memory_types[memory_type_index].info.heap_index;
         //Do not try to create a block if the heap is smaller than the required size (avoids validation warnings).
-        let memory_type = &mut self.memory_types[memory_type_index];
-        let allocation = if size > self.memory_heaps[memory_type.heap_index].size {
-            Err(AllocationError::OutOfMemory)
+        if desc.requirements.size > self.memory_heaps[heap_index].size {
+            return Err(AllocationError::OutOfMemory);
+        }
+
+        let heap_size_limit = self.heap_size_limits[heap_index];
+        if self.memory_budget == MemoryBudgetMode::Enforce {
+            self.refresh_budget_snapshot();
+        }
+        let budget_snapshot = self.budget_snapshot.as_ref();
+        let heap_block_bytes = if heap_size_limit.is_some() || budget_snapshot.is_some() {
+            self.heap_usage(heap_index).0
         } else {
-            memory_type.allocate(
-                &self.device,
-                desc,
-                self.buffer_image_granularity,
-                #[cfg(feature = "std")]
-                backtrace.clone(),
-                &self.allocation_sizes,
-            )
+            0
+        };
+        let device_memory_count = self.device_memory_count();
+        let mut check_new_block = |size| {
+            limits::check_allocation_count(device_memory_count, self.max_memory_allocation_count)?;
+            if let Some(limit) = heap_size_limit {
+                if heap_block_bytes + size > limit {
+                    debug!(
+                        "Refusing to create a memory block of {size} bytes in heap {heap_index}: exceeds heap size limit of {limit} bytes."
+                    );
+                    return Err(AllocationError::OutOfMemory);
+                }
+            }
+            if let Some(budget_snapshot) = budget_snapshot {
+                budget::check_heap_budget(budget_snapshot, heap_index, heap_block_bytes, size)?;
+            }
+            Ok(())
         };
 
-        if desc.location == MemoryLocation::CpuToGpu {
-            if allocation.is_err() {
-                let mem_loc_preferred_bits =
-                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        // Dedicated allocations still get a block of their own without
+        // `VK_KHR_dedicated_allocation`, the driver just isn't told about it.
+        let allocation_scheme = if self.supports_dedicated_allocation() {
+            desc.allocation_scheme
+        } else {
+            AllocationScheme::GpuAllocatorManaged
+        };
 
-                let memory_type_index_opt =
-                    self.find_memorytype_index(&desc.requirements, mem_loc_preferred_bits);
+        let memory_block_sizes = self.memory_block_sizes();
 
-                let memory_type_index = match memory_type_index_opt {
-                    Some(x) => x as usize,
-                    None => return Err(AllocationError::NoCompatibleMemoryTypeFound),
-                };
+        let memory_type = &mut self.memory_types[memory_type_index];
+        let sub_allocation = memory_type.allocate(
+            &self.device,
+            &mut self.fault_injector,
+            &mut check_new_block,
+            &SubAllocationDesc {
+                name: desc.name,
+                size,
+                alignment,
+                allocation_type,
+                granularity: self.buffer_image_granularity,
+                dedicated: dedicated_allocation,
+                block_params: BlockParams {
+                    allocation_scheme,
+                    export_handle_types: desc.export_handle_types,
+                    priority: memory_priority::block_priority(
+                        desc.priority.filter(|_| self.memory_priority),
+                    ),
+                    device_mask: normalized_device_mask(
+                        desc.device_mask,
+                        self.physical_device_count,
+                    ),
+                    buffer_device_address: desc
+                        .buffer_device_address
+                        .unwrap_or(self.buffer_device_address.is_some()),
+                },
+                #[cfg(feature = "std")]
+                backtrace,
+            },
+            &memory_block_sizes,
+        )?;
 
-                self.memory_types[memory_type_index].allocate(
-                    &self.device,
-                    desc,
-                    self.buffer_image_granularity,
-                    #[cfg(feature = "std")]
-                    backtrace,
-                    &self.allocation_sizes,
-                )
-            } else {
-                allocation
+        // With on-demand mapping, persistently mapped allocations hold a mapping until they are
+        // freed.  This includes `GpuOnly` allocations on unified memory devices, which are
+        // guaranteed a mapped pointer, see `allocate()`.
+        let persistently_mapped = desc.persistently_mapped
+            || self.unified_memory
+                && desc.location == MemoryLocation::GpuOnly
+                && desc.memory_properties.is_none();
+        let holds_mapping =
+            memory_type.map_on_demand && persistently_mapped && memory_type.info.mappable;
+        let mapped_ptr = if holds_mapping {
+            match memory_type.map(&self.device, sub_allocation.memory_block_index) {
+                Ok(mapped_ptr) => Some(SendSyncPtr(mapping::offset_ptr(
+                    mapped_ptr,
+                    sub_allocation.offset,
+                ))),
+                Err(e) => {
+                    memory_type.free(
+                        &self.device,
+                        &mut self.fault_injector,
+                        sub_allocation.memory_block_index,
+                        Some(sub_allocation.chunk_id),
+                    )?;
+                    return Err(e);
+                }
             }
         } else {
-            allocation
+            sub_allocation.mapped_ptr
+        };
+
+        let memory_properties = memory_type.info.memory_properties;
+
+        if let Some(budget_group) = desc.budget_group {
+            self.budget_group_state_mut(budget_group)?.allocated_bytes += desc.requirements.size;
         }
+
+        Ok(Allocation {
+            chunk_id: Some(sub_allocation.chunk_id),
+            offset: sub_allocation.offset,
+            size: desc.requirements.size,
+            memory_block_index: sub_allocation.memory_block_index,
+            memory_type_index,
+            device_memory: sub_allocation.memory,
+            mapped_ptr,
+            holds_mapping,
+            map_count: 0,
+            memory_properties,
+            name: Some(desc.name.into()),
+            dedicated_allocation,
+            budget_group: desc.budget_group,
+            used_fallback: false,
+            export_handle_types: desc.export_handle_types,
+            imported: false,
+            device_address: None,
+        })
     }
 
+    #[allow(clippy::needless_pass_by_value)]
     pub fn free(&mut self, allocation: Allocation) -> Result<()> {
+        self.free_allocation(&allocation)
+    }
+
+    /// Frees `allocation` without consuming it, so that callers can keep it around if this fails.
+    fn free_allocation(&mut self, allocation: &Allocation) -> Result<()> {
         if self.debug_settings.log_frees {
             let name = allocation.name.as_deref().unwrap_or("<null>");
             debug!("Freeing `{name}`.");
@@ -894,7 +1274,31 @@ impl Allocator {
             return Ok(());
         }
 
-        self.memory_types[allocation.memory_type_index].free(allocation, &self.device)?;
+        // Check the budget group first, so that a foreign group does not leave the memory freed
+        // but still counted.
+        if let Some(budget_group) = allocation.budget_group {
+            self.budget_group_state(budget_group)?;
+        }
+
+        let memory_type = &mut self.memory_types[allocation.memory_type_index];
+        if allocation.holds_mapping {
+            memory_type.unmap(&self.device, allocation.memory_block_index)?;
+        }
+        // Release mappings through `Allocator::map()` that were never balanced by `unmap()`.
+        for _ in 0..allocation.map_count {
+            memory_type.unmap(&self.device, allocation.memory_block_index)?;
+        }
+
+        self.memory_types[allocation.memory_type_index].free(
+            &self.device,
+            &mut self.fault_injector,
+            allocation.memory_block_index,
+            allocation.chunk_id,
+        )?;
+
+        if let Some(budget_group) = allocation.budget_group {
+            self.budget_group_state_mut(budget_group)?.allocated_bytes -= allocation.size;
+        }
 
         Ok(())
     }
@@ -906,82 +1310,68 @@ impl Allocator {
             return Ok(());
         }
 
-        let mem_type = &mut self.memory_types[allocation.memory_type_index];
-        let mem_block = mem_type.memory_blocks[allocation.memory_block_index]
-            .as_mut()
-            .ok_or_else(|| AllocationError::Internal("Memory block must be Some.".into()))?;
-
-        mem_block
-            .sub_allocator
-            .rename_allocation(allocation.chunk_id, name)?;
-
-        Ok(())
+        self.memory_types[allocation.memory_type_index].rename_allocation(
+            allocation.memory_block_index,
+            allocation.chunk_id,
+            name,
+        )
     }
 
     pub fn report_memory_leaks(&self, log_level: Level) {
-        for (mem_type_i, mem_type) in self.memory_types.iter().enumerate() {
-            for (block_i, mem_block) in mem_type.memory_blocks.iter().enumerate() {
-                if let Some(mem_block) = mem_block {
-                    mem_block
-                        .sub_allocator
-                        .report_memory_leaks(log_level, mem_type_i, block_i);
-                }
-            }
+        for mem_type in &self.memory_types {
+            mem_type.report_memory_leaks(log_level);
         }
     }
 
+    /// Returns the preferences that pick the memory type of `desc`.
+    fn memory_property_preferences<'b>(
+        &'b self,
+        desc: &AllocationCreateDesc<'b>,
+    ) -> &'b MemoryPropertyPreferences {
+        desc.memory_properties
+            .unwrap_or_else(|| self.memory_location_preferences.get(desc.location))
+    }
+
+    /// Returns the cheapest memory type in `memory_type_bits` that has all `required` flags,
+    /// according to [`MemoryTypeFilter::cost()`].  Protected memory types are only considered if
+    /// [`vk::MemoryPropertyFlags::PROTECTED`] is required, and AMD device-coherent or uncached
+    /// memory types only if one of their flags is.
     fn find_memorytype_index(
         &self,
-        memory_req: &vk::MemoryRequirements,
-        flags: vk::MemoryPropertyFlags,
+        memory_type_bits: u32,
+        required: vk::MemoryPropertyFlags,
+        filter: &MemoryTypeFilter,
     ) -> Option<u32> {
+        // These need `VkPhysicalDeviceCoherentMemoryFeaturesAMD::deviceCoherentMemory`, and are
+        // slow even then.
+        let amd_coherent = vk::MemoryPropertyFlags::DEVICE_COHERENT_AMD
+            | vk::MemoryPropertyFlags::DEVICE_UNCACHED_AMD;
+
         self.memory_types
             .iter()
-            .find(|memory_type| {
-                (1 << memory_type.memory_type_index) & memory_req.memory_type_bits != 0
-                    && memory_type.memory_properties.contains(flags)
+            .filter(|memory_type| {
+                let flags = memory_type.info.memory_properties;
+                (1 << memory_type.memory_type_index) & memory_type_bits & filter.memory_type_mask
+                    != 0
+                    && flags.contains(required)
+                    && flags.contains(vk::MemoryPropertyFlags::PROTECTED)
+                        == required.contains(vk::MemoryPropertyFlags::PROTECTED)
+                    && (!flags.intersects(amd_coherent) || required.intersects(amd_coherent))
             })
+            .min_by_key(|memory_type| filter.cost(memory_type.info.memory_properties))
             .map(|memory_type| memory_type.memory_type_index as _)
     }
 
     pub fn generate_report(&self) -> AllocatorReport {
-        let mut allocations = vec![];
-        let mut blocks = vec![];
-        let mut total_capacity_bytes = 0;
-
-        for memory_type in &self.memory_types {
-            for block in memory_type.memory_blocks.iter().flatten() {
-                total_capacity_bytes += block.size;
-                let first_allocation = allocations.len();
-                allocations.extend(block.sub_allocator.report_allocations());
-                blocks.push(MemoryBlockReport {
-                    size: block.size,
-                    allocations: first_allocation..allocations.len(),
-                });
-            }
-        }
-
-        let total_allocated_bytes = allocations.iter().map(|report| report.size).sum();
-
-        AllocatorReport {
-            allocations,
-            blocks,
-            total_allocated_bytes,
-            total_capacity_bytes,
-        }
+        let mut report = allocator::memory_type::generate_report(&self.memory_types);
+        report.budget_groups = self.budget_group_reports();
+        report.fallback_allocations = self.fallback_allocations;
+        report
     }
 
     /// Current total capacity of memory blocks allocated on the device, in bytes
     pub fn capacity(&self) -> u64 {
-        let mut total_capacity_bytes = 0;
-
-        for memory_type in &self.memory_types {
-            for block in memory_type.memory_blocks.iter().flatten() {
-                total_capacity_bytes += block.size;
-            }
-        }
-
-        total_capacity_bytes
+        self.memory_types.iter().map(MemoryType::capacity).sum()
     }
 }
 
@@ -993,12 +1383,22 @@ impl Drop for Allocator {
 
         // Free all remaining memory blocks
         for mem_type in self.memory_types.iter_mut() {
-            for mem_block in mem_type.memory_blocks.iter_mut() {
-                let block = mem_block.take();
-                if let Some(block) = block {
-                    block.destroy(&self.device);
-                }
-            }
+            mem_type.destroy_blocks(&self.device);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::normalized_device_mask;
+
+    #[test]
+    fn all_devices_mask_is_normalized() {
+        assert_eq!(normalized_device_mask(0, 1), 0);
+        assert_eq!(normalized_device_mask(0b1, 1), 0);
+        assert_eq!(normalized_device_mask(0b11, 2), 0);
+        assert_eq!(normalized_device_mask(0b01, 2), 0b01);
+        assert_eq!(normalized_device_mask(u32::MAX, 32), 0);
+        assert_eq!(normalized_device_mask(0b1, 32), 0b1);
+    }
+}
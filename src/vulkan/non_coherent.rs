@@ -0,0 +1,122 @@
+use core::ops::Range;
+
+use ash::vk;
+
+use super::{Allocation, Allocator};
+use crate::{
+    allocator::{align_down, align_up},
+    AllocationError, Result,
+};
+
+/// Returns [`true`] for host-visible memory that needs explicit flushes and invalidations.
+pub(super) fn is_non_coherent(memory_properties: vk::MemoryPropertyFlags) -> bool {
+    memory_properties.contains(vk::MemoryPropertyFlags::HOST_VISIBLE)
+        && !memory_properties.contains(vk::MemoryPropertyFlags::HOST_COHERENT)
+}
+
+/// Widens `range` of a memory block to whole atoms of `atom_size` bytes.
+///
+/// Non-coherent allocations start and end on atom boundaries, see `allocate_in_memory_type()`, but
+/// blocks of a size that is not a multiple of `atom_size` end within an atom.  Ranges are clamped
+/// to the end of the block there, which is allowed in place of an atom boundary.
+fn atom_range(range: Range<u64>, atom_size: u64, block_size: u64) -> Range<u64> {
+    align_down(range.start, atom_size)..align_up(range.end, atom_size).min(block_size)
+}
+
+impl Allocator {
+    /// Makes host writes to `range`, relative to the start of `allocation`, available to the
+    /// device.
+    ///
+    /// Only needed for memory without [`vk::MemoryPropertyFlags::HOST_COHERENT`], and a no-op
+    /// otherwise.  The range is widened to whole `nonCoherentAtomSize` atoms, which never overlap
+    /// with other allocations.  Fails with [`AllocationError::FailedToMap`] if the memory is not
+    /// mapped.
+    pub fn flush(&self, allocation: &Allocation, range: Range<u64>) -> Result<()> {
+        let Some(memory_range) = self.mapped_memory_range(allocation, range)? else {
+            return Ok(());
+        };
+
+        unsafe { self.device.flush_mapped_memory_ranges(&[memory_range]) }
+            .map_err(|e| AllocationError::Internal(format!("Failed to flush memory: {e}")))
+    }
+
+    /// Makes device writes to `range`, relative to the start of `allocation`, visible to the
+    /// host.
+    ///
+    /// Only needed for memory without [`vk::MemoryPropertyFlags::HOST_COHERENT`], and a no-op
+    /// otherwise.  The range is widened to whole `nonCoherentAtomSize` atoms, which never overlap
+    /// with other allocations.  Fails with [`AllocationError::FailedToMap`] if the memory is not
+    /// mapped.
+    pub fn invalidate(&self, allocation: &Allocation, range: Range<u64>) -> Result<()> {
+        let Some(memory_range) = self.mapped_memory_range(allocation, range)? else {
+            return Ok(());
+        };
+
+        unsafe { self.device.invalidate_mapped_memory_ranges(&[memory_range]) }
+            .map_err(|e| AllocationError::Internal(format!("Failed to invalidate memory: {e}")))
+    }
+
+    /// Returns the atom-aligned [`vk::MappedMemoryRange`] covering `range` of `allocation`, or
+    /// [`None`] if there is nothing to flush or invalidate.
+    fn mapped_memory_range(
+        &self,
+        allocation: &Allocation,
+        range: Range<u64>,
+    ) -> Result<Option<vk::MappedMemoryRange<'static>>> {
+        if range.start > range.end || range.end > allocation.size {
+            return Err(AllocationError::InvalidRange(range));
+        }
+
+        if allocation.is_null()
+            || range.is_empty()
+            || !is_non_coherent(allocation.memory_properties)
+        {
+            return Ok(None);
+        }
+
+        // With `AllocatorCreateDesc::map_on_demand`, the block may not be mapped at all.
+        if !self.memory_types[allocation.memory_type_index].is_mapped(allocation.memory_block_index)
+        {
+            return Err(AllocationError::FailedToMap(
+                "Memory has to be mapped to be flushed or invalidated".into(),
+            ));
+        }
+
+        let memory_type = &self.memory_types[allocation.memory_type_index];
+        let block_size = memory_type
+            .block_size(allocation.memory_block_index)
+            .ok_or_else(|| AllocationError::Internal("Memory block must be Some.".into()))?;
+        let Range { start, end } = atom_range(
+            allocation.offset + range.start..allocation.offset + range.end,
+            self.non_coherent_atom_size,
+            block_size,
+        );
+
+        Ok(Some(
+            vk::MappedMemoryRange::default()
+                .memory(allocation.device_memory)
+                .offset(start)
+                .size(end - start),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atom_range_is_widened_to_whole_atoms() {
+        assert_eq!(atom_range(0..64, 64, 1024), 0..64);
+        assert_eq!(atom_range(10..20, 64, 1024), 0..64);
+        assert_eq!(atom_range(100..200, 64, 1024), 64..256);
+    }
+
+    #[test]
+    fn atom_range_is_clamped_to_the_block() {
+        // The last atom of a 1000 byte block ends at 1024, past the end of the block.
+        assert_eq!(atom_range(900..1000, 64, 1000), 896..1000);
+        assert_eq!(atom_range(990..995, 64, 1000), 960..1000);
+        assert_eq!(atom_range(0..1000, 64, 1000), 0..1000);
+    }
+}
@@ -0,0 +1,428 @@
+use ash::vk;
+use log::warn;
+
+use super::{
+    capabilities::Maintenance4, Allocation, AllocationCreateDesc, AllocationScheme, Allocator,
+    BudgetGroup, MemoryPropertyPreferences,
+};
+use crate::{AllocationError, MemoryLocation, Result};
+
+/// Describes how to allocate the memory of a resource created by [`Allocator::create_buffer()`]
+/// or [`Allocator::create_image()`].  See [`AllocationCreateDesc`] for the meaning of every
+/// field.
+#[derive(Clone, Debug)]
+pub struct ResourceAllocationDesc<'a> {
+    pub name: &'a str,
+    pub location: MemoryLocation,
+    /// Give the resource its own, dedicated `VkDeviceMemory` instead of sub-allocating it, even if
+    /// the driver does not prefer that.
+    pub dedicated: bool,
+    pub budget_group: Option<BudgetGroup>,
+    pub memory_properties: Option<&'a MemoryPropertyPreferences>,
+    pub persistently_mapped: bool,
+    pub export_handle_types: vk::ExternalMemoryHandleTypeFlags,
+    pub priority: Option<f32>,
+    pub protected: bool,
+    pub device_mask: u32,
+}
+
+fn vk_error(function: &str, e: vk::Result) -> AllocationError {
+    match e {
+        vk::Result::ERROR_OUT_OF_DEVICE_MEMORY => AllocationError::OutOfMemory,
+        e => AllocationError::Internal(format!("Unexpected error in {function}: {e:?}")),
+    }
+}
+
+impl Allocator {
+    fn allocation_create_desc<'a>(
+        desc: &ResourceAllocationDesc<'a>,
+        requirements: vk::MemoryRequirements,
+        linear: bool,
+        dedicated_scheme: Option<AllocationScheme>,
+        buffer_device_address: bool,
+    ) -> AllocationCreateDesc<'a> {
+        AllocationCreateDesc {
+            name: desc.name,
+            requirements,
+            location: desc.location,
+            linear,
+            allocation_scheme: dedicated_scheme.unwrap_or(AllocationScheme::GpuAllocatorManaged),
+            budget_group: desc.budget_group,
+            memory_properties: desc.memory_properties,
+            persistently_mapped: desc.persistently_mapped,
+            export_handle_types: desc.export_handle_types,
+            priority: desc.priority,
+            protected: desc.protected,
+            device_mask: desc.device_mask,
+            // Never fall back to `AllocatorCreateDesc::buffer_device_address`, the usage of the
+            // resource is known.
+            buffer_device_address: Some(buffer_device_address),
+        }
+    }
+
+    /// Frees the memory of a resource that could not be created or bound to it, without hiding
+    /// that error behind a failure to free.
+    fn free_after_failed_resource(&mut self, allocation: Allocation) {
+        if let Err(e) = self.free(allocation) {
+            warn!("Failed to free memory after failing to create or bind its resource: {e}");
+        }
+    }
+
+    /// Returns the memory requirements of a resource, and whether it should get a dedicated
+    /// allocation.  `requirements2` fills in the requirements with
+    /// `vkGet*MemoryRequirements2` if that is supported, and `requirements` queries them without
+    /// the dedicated requirements otherwise.  Near `maxMemoryAllocationCount`, only required
+    /// dedicated allocations are honored.
+    fn memory_requirements(
+        &self,
+        requirements: impl FnOnce() -> vk::MemoryRequirements,
+        requirements2: impl FnOnce(&mut vk::MemoryRequirements2<'_>),
+    ) -> (vk::MemoryRequirements, bool) {
+        if !self.supports_memory_requirements2() {
+            return (requirements(), false);
+        }
+        self.memory_requirements2(requirements2)
+    }
+
+    /// Returns the memory requirements filled in by `requirements2`, and whether the resource
+    /// should get a dedicated allocation.
+    fn memory_requirements2(
+        &self,
+        requirements2: impl FnOnce(&mut vk::MemoryRequirements2<'_>),
+    ) -> (vk::MemoryRequirements, bool) {
+        let mut dedicated_requirements = vk::MemoryDedicatedRequirements::default();
+        let mut requirements =
+            vk::MemoryRequirements2::default().push_next(&mut dedicated_requirements);
+        requirements2(&mut requirements);
+        let requirements = requirements.memory_requirements;
+
+        (
+            requirements,
+            dedicated_requirements.requires_dedicated_allocation == vk::TRUE
+                || dedicated_requirements.prefers_dedicated_allocation == vk::TRUE
+                    && !self.is_near_allocation_count_limit(),
+        )
+    }
+
+    /// Returns the memory requirements of `buffer`, and whether it should get a dedicated
+    /// allocation.
+    fn buffer_memory_requirements(&self, buffer: vk::Buffer) -> (vk::MemoryRequirements, bool) {
+        self.memory_requirements(
+            || unsafe { self.device.get_buffer_memory_requirements(buffer) },
+            |requirements| unsafe {
+                self.device.get_buffer_memory_requirements2(
+                    &vk::BufferMemoryRequirementsInfo2::default().buffer(buffer),
+                    requirements,
+                )
+            },
+        )
+    }
+
+    /// Returns the memory requirements of `image`, and whether it should get a dedicated
+    /// allocation.
+    fn image_memory_requirements(&self, image: vk::Image) -> (vk::MemoryRequirements, bool) {
+        self.memory_requirements(
+            || unsafe { self.device.get_image_memory_requirements(image) },
+            |requirements| unsafe {
+                self.device.get_image_memory_requirements2(
+                    &vk::ImageMemoryRequirementsInfo2::default().image(image),
+                    requirements,
+                )
+            },
+        )
+    }
+
+    /// Returns the memory requirements of a buffer created with `create_info` without creating
+    /// it, and whether it should get a dedicated allocation.  Needs `maintenance4`.
+    fn buffer_create_info_memory_requirements(
+        &self,
+        create_info: &vk::BufferCreateInfo<'_>,
+    ) -> Option<(vk::MemoryRequirements, bool)> {
+        let maintenance4 = self.maintenance4.as_ref()?;
+        let info = vk::DeviceBufferMemoryRequirements::default().create_info(create_info);
+        Some(self.memory_requirements2(|requirements| unsafe {
+            match maintenance4 {
+                Maintenance4::Core => self
+                    .device
+                    .get_device_buffer_memory_requirements(&info, requirements),
+                Maintenance4::Extension(device) => {
+                    device.get_device_buffer_memory_requirements(&info, requirements)
+                }
+            }
+        }))
+    }
+
+    /// Returns the memory requirements of an image created with `create_info` without creating
+    /// it, and whether it should get a dedicated allocation.  Needs `maintenance4`.
+    fn image_create_info_memory_requirements(
+        &self,
+        create_info: &vk::ImageCreateInfo<'_>,
+    ) -> Option<(vk::MemoryRequirements, bool)> {
+        let maintenance4 = self.maintenance4.as_ref()?;
+        let info = vk::DeviceImageMemoryRequirements::default().create_info(create_info);
+        Some(self.memory_requirements2(|requirements| unsafe {
+            match maintenance4 {
+                Maintenance4::Core => self
+                    .device
+                    .get_device_image_memory_requirements(&info, requirements),
+                Maintenance4::Extension(device) => {
+                    device.get_device_image_memory_requirements(&info, requirements)
+                }
+            }
+        }))
+    }
+
+    /// Replaces [`AllocationScheme::AutoBuffer`] and [`AllocationScheme::AutoImage`] with the
+    /// scheme they stand for.
+    pub(super) fn resolve_allocation_scheme(
+        &self,
+        allocation_scheme: AllocationScheme,
+    ) -> AllocationScheme {
+        match allocation_scheme {
+            AllocationScheme::AutoBuffer(buffer) => {
+                if self.buffer_memory_requirements(buffer).1 {
+                    AllocationScheme::DedicatedBuffer(buffer)
+                } else {
+                    AllocationScheme::GpuAllocatorManaged
+                }
+            }
+            AllocationScheme::AutoImage(image) => {
+                if self.image_memory_requirements(image).1 {
+                    AllocationScheme::DedicatedImage(image)
+                } else {
+                    AllocationScheme::GpuAllocatorManaged
+                }
+            }
+            allocation_scheme => allocation_scheme,
+        }
+    }
+
+    /// Creates a buffer, allocates memory for it and binds that memory to it.
+    ///
+    /// Memory requirements are queried with `vkGetBufferMemoryRequirements2`, unless
+    /// [`super::AllocatorCreateDesc::device_capabilities`] lacks Vulkan 1.1.  With
+    /// [`super::DeviceCapabilities::maintenance4`], they are queried with
+    /// `vkGetDeviceBufferMemoryRequirements` instead, and memory that is not dedicated to the
+    /// buffer is allocated before the buffer is created.  The buffer gets a dedicated allocation
+    /// if requested, or if the driver prefers or requires one.
+    ///
+    /// Buffers with [`vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS`] get memory with
+    /// [`AllocationCreateDesc::buffer_device_address`], and their address is available through
+    /// [`Allocation::device_address()`].  With [`ResourceAllocationDesc::export_handle_types`],
+    /// the buffer is checked to be exportable with `vkGetPhysicalDeviceExternalBufferProperties`
+    /// first.  Release both with [`Allocator::destroy_buffer()`].
+    pub fn create_buffer(
+        &mut self,
+        create_info: &vk::BufferCreateInfo<'_>,
+        desc: &ResourceAllocationDesc<'_>,
+    ) -> Result<(vk::Buffer, Allocation)> {
+        let export_dedicated =
+            self.check_exportable_buffer(create_info, desc.export_handle_types)?;
+        let buffer_device_address = create_info
+            .usage
+            .contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS);
+        let allocation_create_desc = |requirements, dedicated: Option<vk::Buffer>| {
+            Self::allocation_create_desc(
+                desc,
+                requirements,
+                true, // Buffers are always linear
+                dedicated.map(AllocationScheme::DedicatedBuffer),
+                buffer_device_address,
+            )
+        };
+
+        let known_requirements = self.buffer_create_info_memory_requirements(create_info);
+        let early_allocation = match known_requirements {
+            Some((requirements, prefers_dedicated))
+                if !(desc.dedicated || prefers_dedicated || export_dedicated) =>
+            {
+                Some(self.allocate(&allocation_create_desc(requirements, None))?)
+            }
+            _ => None,
+        };
+
+        let buffer = match unsafe { self.device.create_buffer(create_info, None) } {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                if let Some(allocation) = early_allocation {
+                    self.free_after_failed_resource(allocation);
+                }
+                return Err(vk_error("vkCreateBuffer", e));
+            }
+        };
+
+        let mut allocation = match early_allocation {
+            Some(allocation) => allocation,
+            None => {
+                let (requirements, prefers_dedicated) =
+                    known_requirements.unwrap_or_else(|| self.buffer_memory_requirements(buffer));
+                let dedicated = desc.dedicated || prefers_dedicated || export_dedicated;
+                match self.allocate(&allocation_create_desc(
+                    requirements,
+                    dedicated.then_some(buffer),
+                )) {
+                    Ok(allocation) => allocation,
+                    Err(e) => {
+                        unsafe { self.device.destroy_buffer(buffer, None) };
+                        return Err(e);
+                    }
+                }
+            }
+        };
+
+        if let Err(e) = unsafe {
+            self.device
+                .bind_buffer_memory(buffer, allocation.memory(), allocation.offset())
+        } {
+            unsafe { self.device.destroy_buffer(buffer, None) };
+            self.free_after_failed_resource(allocation);
+            return Err(vk_error("vkBindBufferMemory", e));
+        }
+
+        // Allocating failed above if the allocator was created without `buffer_device_address`.
+        if let Some(fns) = self
+            .buffer_device_address
+            .as_ref()
+            .filter(|_| buffer_device_address)
+        {
+            allocation.device_address = Some(unsafe {
+                fns.get_buffer_device_address(
+                    &self.device,
+                    &vk::BufferDeviceAddressInfo::default().buffer(buffer),
+                )
+            });
+        }
+
+        Ok((buffer, allocation))
+    }
+
+    /// Creates an image, allocates memory for it and binds that memory to it.
+    ///
+    /// Memory requirements are queried with `vkGetImageMemoryRequirements2`, unless
+    /// [`super::AllocatorCreateDesc::device_capabilities`] lacks Vulkan 1.1.  With
+    /// [`super::DeviceCapabilities::maintenance4`], they are queried with
+    /// `vkGetDeviceImageMemoryRequirements` instead, and memory that is not dedicated to the
+    /// image is allocated before the image is created.  The image gets a dedicated allocation if
+    /// requested, or if the driver prefers or requires one.
+    ///
+    /// Images with [`vk::ImageCreateFlags::DISJOINT`] planes are not supported.  With
+    /// [`ResourceAllocationDesc::export_handle_types`], the image is checked to be exportable
+    /// with `vkGetPhysicalDeviceImageFormatProperties2` first.  Release both with
+    /// [`Allocator::destroy_image()`].
+    pub fn create_image(
+        &mut self,
+        create_info: &vk::ImageCreateInfo<'_>,
+        desc: &ResourceAllocationDesc<'_>,
+    ) -> Result<(vk::Image, Allocation)> {
+        let export_dedicated =
+            self.check_exportable_image(create_info, desc.export_handle_types)?;
+        let linear = create_info.tiling == vk::ImageTiling::LINEAR;
+
+        let known_requirements = self.image_create_info_memory_requirements(create_info);
+        let early_allocation = match known_requirements {
+            Some((requirements, prefers_dedicated))
+                if !(desc.dedicated || prefers_dedicated || export_dedicated) =>
+            {
+                Some(self.allocate(&Self::allocation_create_desc(
+                    desc,
+                    requirements,
+                    linear,
+                    None,
+                    false,
+                ))?)
+            }
+            _ => None,
+        };
+
+        let image = match unsafe { self.device.create_image(create_info, None) } {
+            Ok(image) => image,
+            Err(e) => {
+                if let Some(allocation) = early_allocation {
+                    self.free_after_failed_resource(allocation);
+                }
+                return Err(vk_error("vkCreateImage", e));
+            }
+        };
+
+        let allocation = match early_allocation {
+            Some(allocation) => allocation,
+            None => {
+                let (requirements, prefers_dedicated) =
+                    known_requirements.unwrap_or_else(|| self.image_memory_requirements(image));
+                let dedicated = desc.dedicated || prefers_dedicated || export_dedicated;
+                match self.allocate(&Self::allocation_create_desc(
+                    desc,
+                    requirements,
+                    linear,
+                    dedicated.then_some(AllocationScheme::DedicatedImage(image)),
+                    false,
+                )) {
+                    Ok(allocation) => allocation,
+                    Err(e) => {
+                        unsafe { self.device.destroy_image(image, None) };
+                        return Err(e);
+                    }
+                }
+            }
+        };
+
+        if let Err(e) = unsafe {
+            self.device
+                .bind_image_memory(image, allocation.memory(), allocation.offset())
+        } {
+            unsafe { self.device.destroy_image(image, None) };
+            self.free_after_failed_resource(allocation);
+            return Err(vk_error("vkBindImageMemory", e));
+        }
+
+        Ok((image, allocation))
+    }
+
+    /// Destroys a buffer created with [`Allocator::create_buffer()`] and frees its memory.
+    ///
+    /// The buffer must not be in use by the device anymore.
+    pub fn destroy_buffer(&mut self, buffer: vk::Buffer, allocation: Allocation) -> Result<()> {
+        unsafe { self.device.destroy_buffer(buffer, None) };
+        self.free(allocation)
+    }
+
+    /// Destroys an image created with [`Allocator::create_image()`] and frees its memory.
+    ///
+    /// The image must not be in use by the device anymore.
+    pub fn destroy_image(&mut self, image: vk::Image, allocation: Allocation) -> Result<()> {
+        unsafe { self.device.destroy_image(image, None) };
+        self.free(allocation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desc() -> ResourceAllocationDesc<'static> {
+        ResourceAllocationDesc {
+            name: "resource",
+            location: MemoryLocation::GpuOnly,
+            dedicated: false,
+            budget_group: None,
+            memory_properties: None,
+            persistently_mapped: false,
+            export_handle_types: vk::ExternalMemoryHandleTypeFlags::empty(),
+            priority: None,
+            protected: false,
+            device_mask: 0,
+        }
+    }
+
+    #[test]
+    fn resources_never_follow_the_allocator_buffer_device_address_flag() {
+        let requirements = vk::MemoryRequirements::default();
+
+        // Images are created with `false`, so their blocks never get `DEVICE_ADDRESS`.
+        let image = Allocator::allocation_create_desc(&desc(), requirements, false, None, false);
+        assert_eq!(image.buffer_device_address, Some(false));
+
+        let buffer = Allocator::allocation_create_desc(&desc(), requirements, true, None, true);
+        assert_eq!(buffer.buffer_device_address, Some(true));
+    }
+}
@@ -0,0 +1,575 @@
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+use ash::vk;
+use log::warn;
+
+use super::{Allocation, AllocationCreateDesc, AllocationScheme, Allocator, BudgetGroup};
+use crate::{AllocationError, MemoryLocation, Result};
+
+/// Describes a sparse buffer or image for [`Allocator::create_sparse_allocation()`].
+#[derive(Clone, Debug)]
+pub struct SparseAllocationCreateDesc<'a> {
+    /// Name of the resource, given to every page for tracking and debugging purposes
+    pub name: &'a str,
+    /// Memory requirements of the sparse resource.  `alignment` is the size of a single page.
+    pub requirements: vk::MemoryRequirements,
+    /// Sparse requirements of the image aspect bound with
+    /// [`Allocator::allocate_sparse_image_tiles()`], if any.  Tile offsets are validated against
+    /// its image granularity.
+    pub image_requirements: Option<vk::SparseImageMemoryRequirements>,
+    /// Location where the pages should be stored
+    pub location: MemoryLocation,
+    /// If the resource is linear (buffer / linear texture) or a regular (tiled) texture.
+    pub linear: bool,
+    pub budget_group: Option<BudgetGroup>,
+    /// Priority of every page, see [`super::AllocationCreateDesc::priority`].
+    pub priority: Option<f32>,
+    /// Allocate every page from protected memory, see [`super::AllocationCreateDesc::protected`].
+    pub protected: bool,
+    /// Physical devices to allocate every page on, see
+    /// [`super::AllocationCreateDesc::device_mask`].
+    pub device_mask: u32,
+}
+
+/// A single page of a sparse image, as bound with a [`vk::SparseImageMemoryBind`].
+#[derive(Clone, Copy, Debug)]
+pub struct SparseImageTile {
+    pub subresource: vk::ImageSubresource,
+    /// Offset of the tile in texels, a multiple of the image granularity.
+    pub offset: vk::Offset3D,
+    /// Extent of the tile in texels, at most the image granularity.
+    pub extent: vk::Extent3D,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum PageKey {
+    Opaque(u64),
+    Image {
+        aspect_mask: u32,
+        mip_level: u32,
+        array_layer: u32,
+        offset: (i32, i32, i32),
+    },
+}
+
+impl From<&SparseImageTile> for PageKey {
+    fn from(tile: &SparseImageTile) -> Self {
+        Self::Image {
+            aspect_mask: tile.subresource.aspect_mask.as_raw(),
+            mip_level: tile.subresource.mip_level,
+            array_layer: tile.subresource.array_layer,
+            offset: (tile.offset.x, tile.offset.y, tile.offset.z),
+        }
+    }
+}
+
+/// Tracks the pages bound to a single sparse resource, created with
+/// [`Allocator::create_sparse_allocation()`].
+///
+/// Every page is a regular [`Allocation`] of one page, sub-allocated from the same memory blocks as
+/// other allocations.  Release all pages with [`Allocator::free_sparse_allocation()`] after the
+/// resource is destroyed.
+#[derive(Debug)]
+pub struct SparseAllocation {
+    name: String,
+    requirements: vk::MemoryRequirements,
+    image_granularity: Option<vk::Extent3D>,
+    location: MemoryLocation,
+    linear: bool,
+    budget_group: Option<BudgetGroup>,
+    priority: Option<f32>,
+    protected: bool,
+    device_mask: u32,
+    pages: BTreeMap<PageKey, Allocation>,
+}
+
+impl SparseAllocation {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Size of a single page, in bytes.
+    pub fn page_size(&self) -> u64 {
+        self.requirements.alignment
+    }
+
+    /// Number of pages that are currently allocated.
+    pub fn resident_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Sum of the sizes of all allocated pages, in bytes.
+    pub fn resident_bytes(&self) -> u64 {
+        self.pages.len() as u64 * self.page_size()
+    }
+
+    /// Returns [`true`] if a page is allocated for the opaque range at `resource_offset`.
+    pub fn is_resident(&self, resource_offset: u64) -> bool {
+        self.pages.contains_key(&PageKey::Opaque(resource_offset))
+    }
+
+    /// Returns [`true`] if a page is allocated for `tile`.
+    pub fn is_tile_resident(&self, tile: &SparseImageTile) -> bool {
+        self.pages.contains_key(&PageKey::from(tile))
+    }
+
+    fn check_resource_offset(&self, resource_offset: u64) -> Result<()> {
+        let page_size = self.page_size();
+        if resource_offset % page_size != 0 {
+            return Err(AllocationError::MisalignedOffset(
+                resource_offset,
+                page_size,
+            ));
+        }
+        if resource_offset >= self.requirements.size {
+            return Err(AllocationError::InvalidRange(
+                resource_offset..resource_offset.saturating_add(page_size),
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_tile(&self, tile: &SparseImageTile) -> Result<()> {
+        let granularity = self
+            .image_granularity
+            .ok_or(AllocationError::InvalidAllocationCreateDesc)?;
+
+        let misaligned = |offset: i32, granularity: u32| {
+            offset < 0 || granularity == 0 || offset as u32 % granularity != 0
+        };
+        if misaligned(tile.offset.x, granularity.width)
+            || misaligned(tile.offset.y, granularity.height)
+            || misaligned(tile.offset.z, granularity.depth)
+            || tile.extent.width > granularity.width
+            || tile.extent.height > granularity.height
+            || tile.extent.depth > granularity.depth
+        {
+            return Err(AllocationError::InvalidAllocationCreateDesc);
+        }
+        Ok(())
+    }
+
+    /// Frees the page of every item in `items` that has one with `free`, and passes the item to
+    /// `unbound` once its page is gone.  Stops at the first page that fails to be freed, which
+    /// stays resident along with the pages of all items after it.
+    fn free_pages<T>(
+        &mut self,
+        items: impl IntoIterator<Item = T>,
+        key: impl Fn(&T) -> PageKey,
+        mut free: impl FnMut(&Allocation) -> Result<()>,
+        mut unbound: impl FnMut(T),
+    ) -> Result<()> {
+        for item in items {
+            let key = key(&item);
+            if let Some(page) = self.pages.get(&key) {
+                free(page)?;
+                self.pages.remove(&key);
+                unbound(item);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Allocator {
+    /// Starts tracking the pages of a sparse buffer or image, without allocating any memory yet.
+    pub fn create_sparse_allocation(
+        &self,
+        desc: &SparseAllocationCreateDesc<'_>,
+    ) -> Result<SparseAllocation> {
+        if desc.requirements.alignment == 0 || desc.requirements.size == 0 {
+            return Err(AllocationError::InvalidAllocationCreateDesc);
+        }
+
+        Ok(SparseAllocation {
+            name: desc.name.into(),
+            requirements: desc.requirements,
+            image_granularity: desc
+                .image_requirements
+                .map(|requirements| requirements.format_properties.image_granularity),
+            location: desc.location,
+            linear: desc.linear,
+            budget_group: desc.budget_group,
+            priority: desc.priority,
+            protected: desc.protected,
+            device_mask: desc.device_mask,
+            pages: BTreeMap::new(),
+        })
+    }
+
+    fn allocate_sparse_page(&mut self, sparse: &SparseAllocation) -> Result<Allocation> {
+        self.allocate(&AllocationCreateDesc {
+            name: &sparse.name,
+            requirements: vk::MemoryRequirements {
+                size: sparse.page_size(),
+                ..sparse.requirements
+            },
+            location: sparse.location,
+            linear: sparse.linear,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            budget_group: sparse.budget_group,
+            memory_properties: None,
+            persistently_mapped: false,
+            export_handle_types: vk::ExternalMemoryHandleTypeFlags::empty(),
+            priority: sparse.priority,
+            protected: sparse.protected,
+            device_mask: sparse.device_mask,
+            buffer_device_address: None,
+        })
+    }
+
+    /// Allocates a page for every key in `keys` that is not resident yet, and returns those keys
+    /// with their new page.  Frees all of them again if any allocation fails, and returns the error
+    /// of that allocation.
+    fn allocate_sparse_pages_for(
+        &mut self,
+        sparse: &mut SparseAllocation,
+        keys: impl IntoIterator<Item = PageKey>,
+    ) -> Result<Vec<(PageKey, Allocation)>> {
+        let mut new_pages: Vec<(PageKey, Allocation)> = Vec::new();
+        for key in keys {
+            if sparse.pages.contains_key(&key) || new_pages.iter().any(|(k, _)| *k == key) {
+                continue;
+            }
+
+            match self.allocate_sparse_page(sparse) {
+                Ok(page) => new_pages.push((key, page)),
+                Err(e) => {
+                    for (_, page) in new_pages {
+                        if let Err(e) = self.free(page) {
+                            warn!("Failed to free sparse page after failing to allocate: {e}");
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(new_pages)
+    }
+
+    /// Allocates a page for every opaque range starting at one of `resource_offsets` that is not
+    /// resident yet, and returns the binds to submit with `vkQueueBindSparse`.
+    ///
+    /// Use this for sparse buffers, and for the mip tail and other opaque regions of sparse images.
+    /// Offsets must be multiples of [`SparseAllocation::page_size()`].
+    pub fn allocate_sparse_pages(
+        &mut self,
+        sparse: &mut SparseAllocation,
+        resource_offsets: &[u64],
+    ) -> Result<Vec<vk::SparseMemoryBind>> {
+        for &resource_offset in resource_offsets {
+            sparse.check_resource_offset(resource_offset)?;
+        }
+
+        let new_pages = self.allocate_sparse_pages_for(
+            sparse,
+            resource_offsets.iter().copied().map(PageKey::Opaque),
+        )?;
+
+        let page_size = sparse.page_size();
+        let mut binds = Vec::with_capacity(new_pages.len());
+        for (key, page) in new_pages {
+            if let PageKey::Opaque(resource_offset) = key {
+                binds.push(vk::SparseMemoryBind {
+                    resource_offset,
+                    size: page_size,
+                    memory: page.device_memory,
+                    memory_offset: page.offset,
+                    flags: vk::SparseMemoryBindFlags::empty(),
+                });
+            }
+            sparse.pages.insert(key, page);
+        }
+        Ok(binds)
+    }
+
+    /// Allocates a page for every tile in `tiles` that is not resident yet, and returns the binds
+    /// to submit with `vkQueueBindSparse`.
+    pub fn allocate_sparse_image_tiles(
+        &mut self,
+        sparse: &mut SparseAllocation,
+        tiles: &[SparseImageTile],
+    ) -> Result<Vec<vk::SparseImageMemoryBind>> {
+        for tile in tiles {
+            sparse.check_tile(tile)?;
+        }
+
+        let new_pages = self.allocate_sparse_pages_for(sparse, tiles.iter().map(PageKey::from))?;
+
+        let mut binds = Vec::with_capacity(new_pages.len());
+        for (key, page) in new_pages {
+            if let Some(tile) = tiles.iter().find(|tile| PageKey::from(*tile) == key) {
+                binds.push(vk::SparseImageMemoryBind {
+                    subresource: tile.subresource,
+                    offset: tile.offset,
+                    extent: tile.extent,
+                    memory: page.device_memory,
+                    memory_offset: page.offset,
+                    flags: vk::SparseMemoryBindFlags::empty(),
+                });
+            }
+            sparse.pages.insert(key, page);
+        }
+        Ok(binds)
+    }
+
+    /// Frees the pages of the opaque ranges starting at `resource_offsets`, and appends the binds
+    /// that unbind them to `binds`.
+    ///
+    /// The memory of freed pages may be handed out again right away, so the unbinds must be
+    /// submitted before the device accesses these ranges of the resource again.  If freeing a page
+    /// fails, the unbinds of the pages freed before it are still appended, and that page and the
+    /// ones after it stay resident.
+    pub fn free_sparse_pages(
+        &mut self,
+        sparse: &mut SparseAllocation,
+        resource_offsets: &[u64],
+        binds: &mut Vec<vk::SparseMemoryBind>,
+    ) -> Result<()> {
+        let page_size = sparse.page_size();
+        sparse.free_pages(
+            resource_offsets.iter().copied(),
+            |&resource_offset| PageKey::Opaque(resource_offset),
+            |page| self.free_allocation(page),
+            |resource_offset| {
+                binds.push(vk::SparseMemoryBind {
+                    resource_offset,
+                    size: page_size,
+                    ..Default::default()
+                });
+            },
+        )
+    }
+
+    /// Frees the pages of `tiles`, and appends the binds that unbind them to `binds`.  See
+    /// [`Allocator::free_sparse_pages()`].
+    pub fn free_sparse_image_tiles(
+        &mut self,
+        sparse: &mut SparseAllocation,
+        tiles: &[SparseImageTile],
+        binds: &mut Vec<vk::SparseImageMemoryBind>,
+    ) -> Result<()> {
+        sparse.free_pages(
+            tiles,
+            |&tile| PageKey::from(tile),
+            |page| self.free_allocation(page),
+            |tile| {
+                binds.push(vk::SparseImageMemoryBind {
+                    subresource: tile.subresource,
+                    offset: tile.offset,
+                    extent: tile.extent,
+                    ..Default::default()
+                });
+            },
+        )
+    }
+
+    /// Frees all pages of a sparse resource.  The resource must have been destroyed, or all of its
+    /// pages unbound, before.  Every page is freed even if freeing one of them fails, and the first
+    /// error is returned.
+    pub fn free_sparse_allocation(&mut self, sparse: SparseAllocation) -> Result<()> {
+        let mut result = Ok(());
+        for page in sparse.pages.into_values() {
+            let freed = self.free(page);
+            if result.is_ok() {
+                result = freed;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sparse_image(image_granularity: Option<vk::Extent3D>) -> SparseAllocation {
+        SparseAllocation {
+            name: "sparse".into(),
+            requirements: vk::MemoryRequirements {
+                size: 1 << 20,
+                alignment: 1 << 16,
+                memory_type_bits: !0,
+            },
+            image_granularity,
+            location: MemoryLocation::GpuOnly,
+            linear: false,
+            budget_group: None,
+            priority: None,
+            protected: false,
+            device_mask: 0,
+            pages: BTreeMap::new(),
+        }
+    }
+
+    fn tile(offset: (i32, i32, i32), extent: (u32, u32, u32)) -> SparseImageTile {
+        SparseImageTile {
+            subresource: vk::ImageSubresource {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                array_layer: 0,
+            },
+            offset: vk::Offset3D {
+                x: offset.0,
+                y: offset.1,
+                z: offset.2,
+            },
+            extent: vk::Extent3D {
+                width: extent.0,
+                height: extent.1,
+                depth: extent.2,
+            },
+        }
+    }
+
+    const GRANULARITY: vk::Extent3D = vk::Extent3D {
+        width: 128,
+        height: 128,
+        depth: 1,
+    };
+
+    #[test]
+    fn check_tile_accepts_aligned_tiles() {
+        let sparse = sparse_image(Some(GRANULARITY));
+        assert!(sparse.check_tile(&tile((0, 0, 0), (128, 128, 1))).is_ok());
+        assert!(sparse
+            .check_tile(&tile((256, 128, 3), (128, 128, 1)))
+            .is_ok());
+        // Tiles at the edge of the image may be smaller than the granularity.
+        assert!(sparse.check_tile(&tile((128, 0, 0), (16, 100, 1))).is_ok());
+    }
+
+    #[test]
+    fn check_tile_rejects_misaligned_and_oversized_tiles() {
+        let sparse = sparse_image(Some(GRANULARITY));
+        for tile in [
+            tile((64, 0, 0), (128, 128, 1)),
+            tile((0, 1, 0), (128, 128, 1)),
+            tile((-128, 0, 0), (128, 128, 1)),
+            tile((0, 0, 0), (256, 128, 1)),
+            tile((0, 0, 0), (128, 128, 2)),
+        ] {
+            assert!(matches!(
+                sparse.check_tile(&tile),
+                Err(AllocationError::InvalidAllocationCreateDesc)
+            ));
+        }
+    }
+
+    #[test]
+    fn check_tile_needs_image_requirements() {
+        let sparse = sparse_image(None);
+        assert!(sparse.check_tile(&tile((0, 0, 0), (128, 128, 1))).is_err());
+
+        let sparse = sparse_image(Some(vk::Extent3D {
+            depth: 0,
+            ..GRANULARITY
+        }));
+        assert!(sparse.check_tile(&tile((0, 0, 0), (128, 128, 0))).is_err());
+    }
+
+    /// Makes the page of every key in `keys` resident, with the page's offset set to its index.
+    fn with_pages(
+        mut sparse: SparseAllocation,
+        keys: impl IntoIterator<Item = PageKey>,
+    ) -> SparseAllocation {
+        for (i, key) in keys.into_iter().enumerate() {
+            let page = Allocation {
+                offset: i as u64,
+                ..Default::default()
+            };
+            sparse.pages.insert(key, page);
+        }
+        sparse
+    }
+
+    #[test]
+    fn check_resource_offset_does_not_overflow() {
+        let sparse = sparse_image(None);
+        let offset = u64::MAX - (1 << 16) + 1;
+        assert!(matches!(
+            sparse.check_resource_offset(offset),
+            Err(AllocationError::InvalidRange(range)) if range == (offset..u64::MAX)
+        ));
+    }
+
+    #[test]
+    fn free_pages_unbinds_every_resident_page_once() {
+        let mut sparse = with_pages(
+            sparse_image(None),
+            [0, 1 << 16, 2 << 16].map(PageKey::Opaque),
+        );
+
+        let mut freed = Vec::new();
+        let mut unbound = Vec::new();
+        sparse
+            .free_pages(
+                [1 << 16, 1 << 16, 3 << 16, 0],
+                |&resource_offset| PageKey::Opaque(resource_offset),
+                |page| {
+                    freed.push(page.offset);
+                    Ok(())
+                },
+                |resource_offset| unbound.push(resource_offset),
+            )
+            .unwrap();
+
+        // Duplicates and pages that are not resident are skipped.
+        assert_eq!(freed, [1, 0]);
+        assert_eq!(unbound, [1 << 16, 0]);
+        assert!(!sparse.is_resident(0));
+        assert!(!sparse.is_resident(1 << 16));
+        assert!(sparse.is_resident(2 << 16));
+        assert_eq!(sparse.resident_pages(), 1);
+    }
+
+    #[test]
+    fn free_pages_keeps_pages_from_the_first_failure_on() {
+        let mut sparse = with_pages(
+            sparse_image(None),
+            [0, 1 << 16, 2 << 16].map(PageKey::Opaque),
+        );
+
+        let mut unbound = Vec::new();
+        let result = sparse.free_pages(
+            [0, 1 << 16, 2 << 16],
+            |&resource_offset| PageKey::Opaque(resource_offset),
+            |page| match page.offset {
+                1 => Err(AllocationError::Internal("free failed".into())),
+                _ => Ok(()),
+            },
+            |resource_offset| unbound.push(resource_offset),
+        );
+
+        assert!(matches!(result, Err(AllocationError::Internal(_))));
+        // Only the page freed before the failure is unbound.
+        assert_eq!(unbound, [0]);
+        assert!(!sparse.is_resident(0));
+        assert!(sparse.is_resident(1 << 16));
+        assert!(sparse.is_resident(2 << 16));
+    }
+
+    #[test]
+    fn free_pages_unbinds_image_tiles() {
+        let first = tile((0, 0, 0), (128, 128, 1));
+        let second = tile((128, 0, 0), (128, 128, 1));
+        let mut sparse = with_pages(
+            sparse_image(Some(GRANULARITY)),
+            [&first, &second].map(PageKey::from),
+        );
+
+        let mut unbound = Vec::new();
+        sparse
+            .free_pages(
+                [&second, &second],
+                |&tile| PageKey::from(tile),
+                |_| Ok(()),
+                |tile| unbound.push(tile.offset.x),
+            )
+            .unwrap();
+
+        assert_eq!(unbound, [128]);
+        assert!(sparse.is_tile_resident(&first));
+        assert!(!sparse.is_tile_resident(&second));
+    }
+}
@@ -86,8 +86,8 @@ impl AllocatorVisualizer {
                                 .filter(|block| block.is_some())
                                 .count();
 
-                            ui.label(format!("properties: {:?}", mem_type.memory_properties));
-                            ui.label(format!("heap index: {}", mem_type.heap_index));
+                            ui.label(format!("properties: {:?}", mem_type.info.memory_properties));
+                            ui.label(format!("heap index: {}", mem_type.info.heap_index));
                             ui.label(format!("total block size: {} KiB", total_block_size / 1024));
                             ui.label(format!("total allocated:  {} KiB", total_allocated / 1024));
                             ui.label(format!("block count: {active_block_count}"));
@@ -105,7 +105,7 @@ impl AllocatorVisualizer {
                                     ));
                                     ui.label(format!(
                                         "vk device memory: 0x{:x}",
-                                        block.device_memory.as_raw()
+                                        block.memory.as_raw()
                                     ));
                                     if let Some(mapped_ptr) = block.mapped_ptr {
                                         ui.label(format!(